@@ -0,0 +1,55 @@
+use crate::{
+    libray::ray,
+    libvec::{point3, vec3},
+};
+
+// Axis-aligned bounding box
+#[derive(Clone, Copy)]
+pub struct aabb {
+    pub minimum: point3,
+    pub maximum: point3,
+}
+
+impl aabb {
+    pub fn from(minimum: point3, maximum: point3) -> aabb {
+        aabb { minimum, maximum }
+    }
+
+    // Slab test against each pair of axis-aligned planes
+    pub fn hit(&self, r: ray, t_min: f64, t_max: f64) -> bool {
+        let mut t_min = t_min;
+        let mut t_max = t_max;
+        for a in 0..3 {
+            let inv_d = 1. / r.direction[a];
+            let mut t0 = (self.minimum[a] - r.origin[a]) * inv_d;
+            let mut t1 = (self.maximum[a] - r.origin[a]) * inv_d;
+            if inv_d < 0. {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+            t_min = t_min.max(t0);
+            t_max = t_max.min(t1);
+            if t_max <= t_min {
+                return false;
+            }
+        }
+        true
+    }
+
+    pub fn translate(&self, offset: vec3) -> aabb {
+        aabb::from(self.minimum + offset, self.maximum + offset)
+    }
+}
+
+pub fn surrounding_box(box0: aabb, box1: aabb) -> aabb {
+    let small = point3::from(
+        box0.minimum.x.min(box1.minimum.x),
+        box0.minimum.y.min(box1.minimum.y),
+        box0.minimum.z.min(box1.minimum.z),
+    );
+    let big = point3::from(
+        box0.maximum.x.max(box1.maximum.x),
+        box0.maximum.y.max(box1.maximum.y),
+        box0.maximum.z.max(box1.maximum.z),
+    );
+    aabb::from(small, big)
+}
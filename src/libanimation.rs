@@ -0,0 +1,316 @@
+use std::ops::{Add, Mul, Sub};
+
+use crate::{
+    libcamera::camera,
+    libvec::{point3, vec3},
+};
+
+#[derive(Clone, Copy)]
+pub enum interpolation {
+    Linear,
+    CatmullRom, // smooth curve through the keys, for camera paths
+}
+
+// Values keyed at points in time, interpolated in between and held beyond the first
+// and last keys unless looping. Cameras animate every parameter of `camera_animation`,
+// objects their position, rotation and scale through `transform`.
+#[derive(Clone)]
+pub struct keyframes<T> {
+    keys: Vec<(f64, T)>,
+    interpolation: interpolation,
+    looping: bool,
+}
+
+impl<T> keyframes<T>
+where
+    T: Copy + Add<Output = T> + Sub<Output = T> + Mul<f64, Output = T>,
+{
+    pub fn from(interpolation: interpolation) -> keyframes<T> {
+        keyframes {
+            keys: Vec::new(),
+            interpolation,
+            looping: false,
+        }
+    }
+
+    // Repeat the keys over the period from the first to the last key, which should hold the
+    // same value. Tangents then wrap around so the curve stays smooth through the seam.
+    pub fn looping(mut self) -> keyframes<T> {
+        self.looping = true;
+        self
+    }
+
+    // Time within the first period of a looping animation
+    fn wrap(&self, time: f64) -> f64 {
+        let n = self.keys.len();
+        if !self.looping || n < 2 {
+            return time;
+        }
+        let first = self.keys[0].0;
+        let period = self.keys[n - 1].0 - first;
+        first + (time - first).rem_euclid(period)
+    }
+
+    pub fn constant(value: T) -> keyframes<T> {
+        keyframes::from(interpolation::Linear).with_key(0., value)
+    }
+
+    // Add a key, keeping them ordered by time and replacing any key at the same time
+    pub fn with_key(mut self, time: f64, value: T) -> keyframes<T> {
+        match self.keys.binary_search_by(|k| k.0.total_cmp(&time)) {
+            Ok(i) => self.keys[i].1 = value,
+            Err(i) => self.keys.insert(i, (time, value)),
+        }
+        self
+    }
+
+    // Values whose convex hull holds the curve from time0 to time1: the values at both ends,
+    // the keys in between and the Bezier control points of every Catmull-Rom segment crossed
+    pub fn control_points(&self, time0: f64, time1: f64) -> Vec<T> {
+        let n = self.keys.len();
+        let (time0, time1) = if self.looping && n > 1 {
+            // A range crossing the seam covers the whole loop
+            let (first, last) = (self.keys[0].0, self.keys[n - 1].0);
+            let (t0, t1) = (self.wrap(time0), self.wrap(time1));
+            if t1 < t0 || time1 - time0 >= last - first {
+                (first, last)
+            } else {
+                (t0, t1)
+            }
+        } else {
+            (time0, time1)
+        };
+        let mut points = vec![self.at(time0), self.at(time1)];
+        for i in 0..n.saturating_sub(1) {
+            let (t0, p0) = self.keys[i];
+            let (t1, p1) = self.keys[i + 1];
+            if t1 <= time0 || t0 >= time1 {
+                continue;
+            }
+            match self.interpolation {
+                interpolation::Linear => points.extend([p0, p1]),
+                interpolation::CatmullRom => {
+                    let third = (t1 - t0) / 3.;
+                    points.extend([
+                        p0,
+                        p0 + self.tangent(i) * third,
+                        p1 - self.tangent(i + 1) * third,
+                        p1,
+                    ]);
+                }
+            }
+        }
+        points
+    }
+
+    pub fn at(&self, time: f64) -> T {
+        let n = self.keys.len();
+        assert!(n > 0, "keyframes without keys");
+        let time = self.wrap(time);
+        if n == 1 || time <= self.keys[0].0 {
+            return self.keys[0].1;
+        }
+        if time >= self.keys[n - 1].0 {
+            return self.keys[n - 1].1;
+        }
+
+        // Segment [i, i + 1] containing time
+        let i = self.keys.partition_point(|k| k.0 <= time) - 1;
+        let (t0, p0) = self.keys[i];
+        let (t1, p1) = self.keys[i + 1];
+        let dt = t1 - t0;
+        let s = (time - t0) / dt;
+
+        match self.interpolation {
+            interpolation::Linear => p0 + (p1 - p0) * s,
+            interpolation::CatmullRom => {
+                // Cubic Hermite with finite difference tangents, which allows uneven key spacing
+                let m0 = self.tangent(i);
+                let m1 = self.tangent(i + 1);
+                let s2 = s * s;
+                let s3 = s2 * s;
+                p0 * (2. * s3 - 3. * s2 + 1.)
+                    + m0 * ((s3 - 2. * s2 + s) * dt)
+                    + p1 * (-2. * s3 + 3. * s2)
+                    + m1 * ((s3 - s2) * dt)
+            }
+        }
+    }
+
+    fn tangent(&self, i: usize) -> T {
+        let n = self.keys.len();
+        let (mut ta, mut pa) = self.keys[i.saturating_sub(1)];
+        let (mut tb, mut pb) = self.keys[(i + 1).min(n - 1)];
+        if self.looping && n > 2 {
+            // The last key stands in for the first, so the neighbours wrap past it
+            let period = self.keys[n - 1].0 - self.keys[0].0;
+            if i == 0 {
+                (ta, pa) = self.keys[n - 2];
+                ta -= period;
+            }
+            if i == n - 1 {
+                (tb, pb) = self.keys[1];
+                tb += period;
+            }
+        }
+        (pb - pa) * (1. / (tb - ta))
+    }
+}
+
+// Keyframed parameters of a thin lens camera
+pub struct camera_animation {
+    pub lookfrom: keyframes<point3>,
+    pub lookat: keyframes<point3>,
+    pub vup: keyframes<vec3>,
+    pub vfov: keyframes<f64>,
+    pub aperture: keyframes<f64>,
+    pub focus_dist: keyframes<f64>,
+    pub iso: Option<f64>, // film speed for scenes lit in cd/m^2, None leaves radiance unscaled
+    pub f_number: Option<f64>, // replaces the aperture, needed to meter a film speed
+}
+
+impl camera_animation {
+    // Start from a still camera, then replace the parameters that move
+    pub fn from(
+        lookfrom: point3,
+        lookat: point3,
+        vup: vec3,
+        vfov: f64,
+        aperture: f64,
+        focus_dist: f64,
+    ) -> camera_animation {
+        camera_animation {
+            lookfrom: keyframes::constant(lookfrom),
+            lookat: keyframes::constant(lookat),
+            vup: keyframes::constant(vup),
+            vfov: keyframes::constant(vfov),
+            aperture: keyframes::constant(aperture),
+            focus_dist: keyframes::constant(focus_dist),
+            iso: None,
+            f_number: None,
+        }
+    }
+
+    // Orbit around lookat at the height of lookfrom, once every `period` seconds. The last
+    // key repeats the first, looping keeps the orbit smooth through it.
+    pub fn turntable(
+        lookfrom: point3,
+        lookat: point3,
+        vfov: f64,
+        aperture: f64,
+        focus_dist: f64,
+        period: f64,
+    ) -> camera_animation {
+        let offset = lookfrom - lookat;
+        let radius = (offset.x * offset.x + offset.z * offset.z).sqrt();
+        let start_angle = offset.z.atan2(offset.x);
+        let keys = 12;
+        let mut orbit = keyframes::from(interpolation::CatmullRom).looping();
+        for k in 0..=keys {
+            let angle = start_angle + 2. * std::f64::consts::PI * k as f64 / keys as f64;
+            let time = period * k as f64 / keys as f64;
+            orbit = orbit.with_key(
+                time,
+                lookat + vec3::from(radius * angle.cos(), offset.y, radius * angle.sin()),
+            );
+        }
+
+        let vup = vec3::from(0., 1., 0.);
+        camera_animation::from(lookfrom, lookat, vup, vfov, aperture, focus_dist)
+            .with_lookfrom(orbit)
+    }
+
+    pub fn with_lookfrom(mut self, lookfrom: keyframes<point3>) -> camera_animation {
+        self.lookfrom = lookfrom;
+        self
+    }
+
+    pub fn with_lookat(mut self, lookat: keyframes<point3>) -> camera_animation {
+        self.lookat = lookat;
+        self
+    }
+
+    pub fn with_vup(mut self, vup: keyframes<vec3>) -> camera_animation {
+        self.vup = vup;
+        self
+    }
+
+    pub fn with_vfov(mut self, vfov: keyframes<f64>) -> camera_animation {
+        self.vfov = vfov;
+        self
+    }
+
+    pub fn with_aperture(mut self, aperture: keyframes<f64>) -> camera_animation {
+        self.aperture = aperture;
+        self
+    }
+
+    pub fn with_focus_dist(mut self, focus_dist: keyframes<f64>) -> camera_animation {
+        self.focus_dist = focus_dist;
+        self
+    }
+
+    pub fn with_iso(mut self, iso: f64) -> camera_animation {
+        self.iso = Some(iso);
+        self
+    }
+
+    pub fn with_f_number(mut self, f_number: f64) -> camera_animation {
+        self.f_number = Some(f_number);
+        self
+    }
+
+    pub fn camera_at(&self, time: f64, aspect_ratio: f64) -> camera {
+        let cam = camera::from(
+            self.lookfrom.at(time),
+            self.lookat.at(time),
+            self.vup.at(time),
+            self.vfov.at(time),
+            aspect_ratio,
+            self.aperture.at(time),
+            self.focus_dist.at(time),
+        );
+        match self.f_number {
+            Some(n) => cam.with_f_number(n),
+            None => cam,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn uneven() -> keyframes<f64> {
+        keyframes::from(interpolation::CatmullRom)
+            .with_key(0., 1.)
+            .with_key(0.5, 3.)
+            .with_key(2., -1.)
+            .with_key(3., 1.)
+    }
+
+    #[test]
+    fn passes_through_the_keys_and_holds_beyond_them() {
+        for curve in [uneven(), uneven().looping()] {
+            for (time, value) in [(0., 1.), (0.5, 3.), (2., -1.)] {
+                assert!((curve.at(time) - value).abs() < 1e-12);
+            }
+        }
+        let linear = keyframes::from(interpolation::Linear)
+            .with_key(1., 2.)
+            .with_key(3., 6.);
+        assert_eq!(linear.at(2.), 4.);
+        assert_eq!(linear.at(-5.), 2.);
+        assert_eq!(linear.at(9.), 6.);
+    }
+
+    #[test]
+    fn looping_is_smooth_through_the_seam() {
+        let curve = uneven().looping();
+        let h = 1e-6;
+        let before = (curve.at(3. - h) - curve.at(3. - 2. * h)) / h;
+        let after = (curve.at(3. + 2. * h) - curve.at(3. + h)) / h;
+        assert!((before - after).abs() < 1e-3);
+        assert!((curve.at(7.25) - curve.at(1.25)).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,170 @@
+use std::f64::consts::PI;
+
+use crate::{
+    libtexture::image_texture,
+    libvec::{random_in_unit_disk, vec3},
+};
+
+// Shape of the lens opening, which out-of-focus highlights take on
+#[derive(Clone)]
+pub enum aperture {
+    Disk,
+    Polygon(polygon_aperture),
+    Image(image_aperture),
+}
+
+impl aperture {
+    // `rotation` of the first blade corner in degrees
+    pub fn polygon(blades: u32, rotation: f64) -> aperture {
+        aperture::Polygon(polygon_aperture::from(blades, rotation))
+    }
+
+    pub fn image(image: &image_texture) -> aperture {
+        aperture::Image(image_aperture::from(image))
+    }
+
+    // Random point on the aperture, within the unit disk in the lens plane
+    pub fn sample(&self) -> vec3 {
+        match self {
+            aperture::Disk => random_in_unit_disk(),
+            aperture::Polygon(p) => p.sample(),
+            aperture::Image(i) => i.sample(),
+        }
+    }
+
+    // Optical vignetting: the lens barrel, a second unit disk shifted by `shift`, clips the
+    // aperture into a cat's-eye toward the edges of the frame. None when the barrel blocks
+    // the sample, which then carries no light and darkens the edges of the frame.
+    pub fn sample_vignetted(&self, shift: vec3) -> Option<vec3> {
+        let p = self.sample();
+        if (p - shift).length_squared() > 1. {
+            return None;
+        }
+        Some(p)
+    }
+}
+
+// Regular polygon formed by `blades` straight aperture blades
+#[derive(Clone)]
+pub struct polygon_aperture {
+    blades: u32,
+    rotation: f64, // in radians
+}
+
+impl polygon_aperture {
+    // `rotation` of the first blade corner in degrees
+    pub fn from(blades: u32, rotation: f64) -> polygon_aperture {
+        assert!(blades >= 3, "an aperture needs at least 3 blades");
+        polygon_aperture {
+            blades,
+            rotation: rotation.to_radians(),
+        }
+    }
+
+    fn vertex(&self, k: u32) -> vec3 {
+        let angle = self.rotation + 2. * PI * k as f64 / self.blades as f64;
+        vec3::from(angle.cos(), angle.sin(), 0.)
+    }
+
+    fn sample(&self) -> vec3 {
+        // All triangles fanning out from the center have the same area
+        let k = ((rand::random::<f64>() * self.blades as f64) as u32).min(self.blades - 1);
+        let mut a = rand::random::<f64>();
+        let mut b = rand::random::<f64>();
+        if a + b > 1. {
+            a = 1. - a;
+            b = 1. - b;
+        }
+        a * self.vertex(k) + b * self.vertex(k + 1)
+    }
+}
+
+// Grayscale aperture mask spanning the square around the unit disk, sampled in proportion
+// to its brightness. Only the part within the disk lets light through.
+#[derive(Clone)]
+pub struct image_aperture {
+    width: usize,
+    height: usize,
+    cdf: Vec<f64>,
+}
+
+impl image_aperture {
+    pub fn from(image: &image_texture) -> image_aperture {
+        let (width, height) = image.dimensions();
+        let mut cdf = Vec::with_capacity(width * height);
+        let mut total = 0.;
+        for j in 0..height {
+            for i in 0..width {
+                let u = (i as f64 + 0.5) / width as f64;
+                let v = 1. - (j as f64 + 0.5) / height as f64;
+                let c = image.value(u, v);
+                let (x, y) = (2. * u - 1., 2. * v - 1.);
+                if x * x + y * y <= 1. {
+                    total += ((c.x + c.y + c.z) / 3.).max(0.);
+                }
+                cdf.push(total);
+            }
+        }
+        image_aperture { width, height, cdf }
+    }
+
+    fn sample(&self) -> vec3 {
+        let total = match self.cdf.last() {
+            Some(&total) if total > 0. => total,
+            _ => return random_in_unit_disk(),
+        };
+        let target = rand::random::<f64>() * total;
+        let index = self
+            .cdf
+            .partition_point(|&c| c <= target)
+            .min(self.cdf.len() - 1);
+
+        // Jitter within the chosen pixel, row 0 being the top of the image. Pixels crossing the
+        // edge of the disk fall back to their center, which lies inside.
+        let i = index % self.width;
+        let j = index / self.width;
+        let at = |dx: f64, dy: f64| {
+            let x = (i as f64 + dx) / self.width as f64;
+            let y = (j as f64 + dy) / self.height as f64;
+            vec3::from(2. * x - 1., 1. - 2. * y, 0.)
+        };
+        let p = at(rand::random::<f64>(), rand::random::<f64>());
+        if p.length_squared() <= 1. {
+            p
+        } else {
+            at(0.5, 0.5)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libvec::color;
+
+    #[test]
+    fn square_image_stays_within_the_unit_disk() {
+        let white = image_texture::from(8, 8, vec![color::from(1., 1., 1.); 64]);
+        let a = aperture::image(&white);
+        for _ in 0..10000 {
+            assert!(a.sample().length_squared() <= 1.);
+        }
+    }
+
+    #[test]
+    fn barrel_blocks_samples_instead_of_replacing_them() {
+        let a = aperture::Disk;
+        // Barrel entirely past the aperture: nothing gets through
+        let shift = vec3::from(2.5, 0., 0.);
+        assert!((0..1000).all(|_| a.sample_vignetted(shift).is_none()));
+
+        // Half overlapping: about 39% of the disk is left, every sample inside both disks
+        let shift = vec3::from(1., 0., 0.);
+        let passed: Vec<vec3> = (0..20000)
+            .filter_map(|_| a.sample_vignetted(shift))
+            .collect();
+        let share = passed.len() as f64 / 20000.;
+        assert!((share - 0.391).abs() < 0.02);
+        assert!(passed.iter().all(|&p| (p - shift).length_squared() <= 1.));
+    }
+}
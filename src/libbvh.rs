@@ -0,0 +1,140 @@
+use crate::{
+    libaabb::{aabb, surrounding_box},
+    libhittable::{hit_record, hittable},
+    libray::ray,
+};
+
+// Bounding volume hierarchy over objects with a box for the whole time range it was built for
+pub struct bvh_node {
+    left: Box<hittable>,
+    right: Box<hittable>,
+    bbox: aabb,
+}
+
+impl bvh_node {
+    // Every object needs a bounding box over time0 to time1. A single object is returned as is.
+    pub fn from(objects: Vec<hittable>, time0: f64, time1: f64) -> hittable {
+        let objects = objects
+            .into_iter()
+            .map(|object| {
+                let bbox = object
+                    .bounding_box(time0, time1)
+                    .expect("no bounding box for an object in a BVH");
+                (bbox, object)
+            })
+            .collect();
+        bvh_node::build(objects)
+    }
+
+    fn build(mut objects: Vec<(aabb, hittable)>) -> hittable {
+        assert!(!objects.is_empty(), "BVH without objects");
+        if objects.len() == 1 {
+            return objects.pop().unwrap().1;
+        }
+
+        // Split at the median along the longest axis of the box around the centers
+        let center = |b: &aabb, axis: i32| 0.5 * (b.minimum[axis] + b.maximum[axis]);
+        let mut centers = aabb::from(objects[0].0.minimum, objects[0].0.minimum);
+        for (b, _) in &objects {
+            let c = 0.5 * (b.minimum + b.maximum);
+            centers = surrounding_box(centers, aabb::from(c, c));
+        }
+        let extent = centers.maximum - centers.minimum;
+        let axis = if extent.x > extent.y && extent.x > extent.z {
+            0
+        } else if extent.y > extent.z {
+            1
+        } else {
+            2
+        };
+        objects.sort_by(|a, b| center(&a.0, axis).total_cmp(&center(&b.0, axis)));
+
+        let bbox = objects.iter().map(|o| o.0).reduce(surrounding_box).unwrap();
+        let right = objects.split_off(objects.len() / 2);
+        hittable::Bvh(bvh_node {
+            left: Box::new(bvh_node::build(objects)),
+            right: Box::new(bvh_node::build(right)),
+            bbox,
+        })
+    }
+
+    pub fn hit<'a>(&'a self, r: ray, t_min: f64, t_max: f64, rec: &mut hit_record<'a>) -> bool {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return false;
+        }
+        let hit_left = self.left.hit_object(r, t_min, t_max, rec);
+        let t_max = if hit_left { rec.t } else { t_max };
+        let hit_right = self.right.hit_object(r, t_min, t_max, rec);
+        hit_left || hit_right
+    }
+
+    pub fn hit_surface<'a>(
+        &'a self,
+        r: ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut hit_record<'a>,
+    ) -> bool {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return false;
+        }
+        let hit_left = self.left.hit_surface(r, t_min, t_max, rec);
+        let t_max = if hit_left { rec.t } else { t_max };
+        let hit_right = self.right.hit_surface(r, t_min, t_max, rec);
+        hit_left || hit_right
+    }
+
+    pub fn transmittance(&self, r: ray, t_min: f64, t_max: f64) -> f64 {
+        if !self.bbox.hit(r, t_min, t_max) {
+            return 1.;
+        }
+        self.left.transmittance(r, t_min, t_max) * self.right.transmittance(r, t_min, t_max)
+    }
+
+    pub fn bounding_box(&self) -> aabb {
+        self.bbox
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libmaterial::{lambertian, material};
+    use crate::libsphere::sphere;
+    use crate::libvec::{color, point3, random_unit_vector, vec3};
+
+    #[test]
+    fn finds_the_same_closest_hit_as_a_linear_search() {
+        let mat = crate::lambertian!(0.5, 0.5, 0.5);
+        let spheres: Vec<(point3, f64)> = (0..200)
+            .map(|_| (vec3::random_range(-10., 10.), 0.2 + rand::random::<f64>()))
+            .collect();
+        let objects = || {
+            spheres
+                .iter()
+                .map(|&(center, radius)| hittable::Sphere(sphere::from(center, radius, &mat)))
+                .collect::<Vec<hittable>>()
+        };
+        let linear = objects();
+        let bvh = bvh_node::from(objects(), 0., 0.);
+
+        for _ in 0..500 {
+            let r = ray::from(point3::new(), random_unit_vector());
+            let mut closest = f64::INFINITY;
+            for object in &linear {
+                let mut rec = hit_record::new();
+                if object.hit(r, 0.001, closest, &mut rec) {
+                    closest = rec.t;
+                }
+            }
+
+            let mut rec = hit_record::new();
+            let hit = bvh.hit_object(r, 0.001, f64::INFINITY, &mut rec);
+            assert_eq!(hit, closest.is_finite());
+            if hit {
+                assert!((rec.t - closest).abs() < 1e-9);
+                assert!(matches!(rec.object, Some(hittable::Sphere(_))));
+            }
+        }
+    }
+}
@@ -1,16 +1,69 @@
+use std::f64::consts::PI;
+
 use crate::{
+    libaperture::aperture,
+    libhittable::hit_record,
+    libhittable_list::hittable_list,
+    liblens::lens_system,
     libray::ray,
-    libvec::{cross, point3, random_in_unit_disk, unit_vector, vec3},
+    libvec::{cross, degrees_to_radians, dot, point3, rotate, unit_vector, vec3},
 };
 
+// Calibration constant of the exposure meter, with lens and vignetting losses
+const EXPOSURE_CALIBRATION: f64 = 1.2;
+// Full frame film height in metres, for a thin lens given no film of its own
+const DEFAULT_FILM_HEIGHT: f64 = 0.024;
+
+#[derive(Clone, Copy)]
+pub enum projection {
+    Perspective,
+    Orthographic(f64), // height of the view in world units
+    Fisheye,           // equidistant, `vfox` spans the image height
+    Equirectangular,   // full 360 by 180 degree panorama
+}
+
+// How the two eyes of a stereo pair share the image
+#[derive(Clone, Copy)]
+pub enum stereo_layout {
+    SideBySide, // left eye on the left half
+    TopBottom,  // left eye on the top half
+}
+
+#[derive(Clone, Copy)]
+struct stereo {
+    interocular: f64,
+    convergence: f64, // distance of zero parallax
+    layout: stereo_layout,
+}
+
 pub struct camera {
     origin: point3,
+    lookat: point3,
     horizontal: vec3,
     vertical: vec3,
     lower_left_corner: point3,
     lens_radius: f64,
     u: vec3,
     v: vec3,
+    w: vec3,
+    vfov: f64, // in radians
+    aspect_ratio: f64,
+    focus_dist: f64,
+    projection: projection,
+    time0: f64, // shutter open and close times
+    time1: f64,
+    aperture: aperture,
+    cat_eye: f64, // strength of optical vignetting at the corners of the frame
+    lens: Option<lens_system>,
+    film_height: Option<f64>, // in scene units, sets the focal length with the field of view
+    f_number: Option<f64>,    // thin lens aperture when set as an f-number
+    iso: Option<f64>,         // film speed, None to leave radiance unscaled
+    shutter_time: f64,        // in seconds
+    ev_compensation: f64,
+    shift_x: f64, // viewport shift in fractions of its width and height
+    shift_y: f64,
+    focal_normal: vec3, // normal of the plane in focus, tilted away from w by a tilt lens
+    stereo: Option<stereo>,
 }
 
 impl camera {
@@ -41,26 +94,648 @@ impl camera {
 
         camera {
             origin,
+            lookat,
             horizontal,
             vertical,
             lower_left_corner,
             lens_radius,
             u,
             v,
+            w,
+            vfov: theta,
+            aspect_ratio,
+            focus_dist,
+            projection: projection::Perspective,
+            time0: 0.,
+            time1: 0.,
+            aperture: aperture::Disk,
+            cat_eye: 0.,
+            lens: None,
+            film_height: None,
+            f_number: None,
+            iso: None,
+            shutter_time: 1.,
+            ev_compensation: 0.,
+            shift_x: 0.,
+            shift_y: 0.,
+            focal_normal: w,
+            stereo: None,
+        }
+    }
+
+    pub fn with_projection(mut self, projection: projection) -> camera {
+        self.projection = projection;
+        self
+    }
+
+    pub fn with_shutter(mut self, open: f64, close: f64) -> camera {
+        self.time0 = open;
+        self.time1 = close;
+        self
+    }
+
+    pub fn with_aperture(mut self, aperture: aperture) -> camera {
+        self.aperture = aperture;
+        self
+    }
+
+    // Clip bokeh toward the frame edges, 0 for none and up to 2 where it vanishes
+    pub fn with_cat_eye(mut self, strength: f64) -> camera {
+        self.cat_eye = strength.clamp(0., 2.);
+        self
+    }
+
+    // Shift the viewport off axis, in fractions of its width and height. Shifting up keeps
+    // verticals parallel while framing the top of a building from a level camera.
+    pub fn with_shift(mut self, shift_x: f64, shift_y: f64) -> camera {
+        self.shift_x = shift_x;
+        self.shift_y = shift_y;
+        self.update_viewport();
+        self
+    }
+
+    // Move the plane of focus, keeping the field of view
+    pub fn with_focus_dist(mut self, focus_dist: f64) -> camera {
+        let scale = focus_dist / self.focus_dist;
+        self.horizontal = scale * self.horizontal;
+        self.vertical = scale * self.vertical;
+        self.focus_dist = focus_dist;
+        self.update_viewport();
+        if let Some(lens) = &mut self.lens {
+            lens.prepare(focus_dist, self.aspect_ratio);
+        }
+        self
+    }
+
+    pub fn focus_dist(&self) -> f64 {
+        self.focus_dist
+    }
+
+    // Focusing on a subject also converges a stereo pair on it
+    fn refocus(mut self, focus_dist: f64) -> camera {
+        if let Some(stereo) = &mut self.stereo {
+            stereo.convergence = focus_dist;
+        }
+        self.with_focus_dist(focus_dist)
+    }
+
+    pub fn with_focus_on_lookat(self) -> camera {
+        let focus_dist = (self.lookat - self.origin).length();
+        self.refocus(focus_dist)
+    }
+
+    // Focus on the surface seen through image position (s, t) when the shutter opens, keeping
+    // the current focus distance when the ray escapes the scene. Media are looked through and
+    // cut-outs are solid where at least half opaque, so the focus holds from frame to frame.
+    // In a stereo pair (s, t) is seen by the eye whose half it falls in, and the pair
+    // converges at the new distance. A lens system focuses along its chief ray.
+    pub fn with_autofocus(mut self, world: &hittable_list, s: f64, t: f64) -> camera {
+        let (s, t, eye) = self.split_eyes(s, t);
+        let r = match &self.lens {
+            Some(lens) => lens.chief_ray(s, t).map(|(o, d)| self.lens_ray(o, d, eye)),
+            None => {
+                // Cast through the center of the lens
+                let lens_radius = self.lens_radius;
+                self.lens_radius = 0.;
+                let r = self.project(s, t, eye);
+                self.lens_radius = lens_radius;
+                r
+            }
+        };
+
+        let mut r = match r {
+            Some(r) => r,
+            None => return self,
+        };
+        r.time = self.time0;
+        let mut rec = hit_record::new();
+        if !world.hit_surface(r, 0.001, f64::INFINITY, &mut rec) {
+            return self;
         }
+        let focus_dist = match self.projection {
+            // Wide projections focus at a distance along each ray rather than on a plane
+            projection::Fisheye | projection::Equirectangular => (rec.p - r.origin).length(),
+            _ => dot(rec.p - self.origin, -self.w),
+        };
+        if focus_dist <= 0. {
+            return self;
+        }
+        self.refocus(focus_dist)
+    }
+
+    // Tilt the plane of focus about the horizontal axis and swing it about the vertical
+    // axis, both in degrees. It still passes through the point at `focus_dist` ahead.
+    pub fn with_tilt(mut self, tilt: f64, swing: f64) -> camera {
+        let n = rotate(self.w, self.u, degrees_to_radians(tilt));
+        self.focal_normal = rotate(n, self.v, degrees_to_radians(swing));
+        self
+    }
+
+    // Render a stereo pair into one image, each eye getting half of it. The vertical field of
+    // view is kept and the width of the view follows the aspect ratio of one eye's half.
+    // With the equirectangular projection this gives an omni-directional stereo (ODS)
+    // panorama, where the convergence distance is not used.
+    pub fn with_stereo(
+        mut self,
+        interocular: f64,
+        convergence: f64,
+        layout: stereo_layout,
+    ) -> camera {
+        let frame_aspect = match self.stereo.map(|s| s.layout) {
+            Some(stereo_layout::SideBySide) => 2. * self.aspect_ratio,
+            Some(stereo_layout::TopBottom) => self.aspect_ratio / 2.,
+            None => self.aspect_ratio,
+        };
+        let eye_aspect = match layout {
+            stereo_layout::SideBySide => frame_aspect / 2.,
+            stereo_layout::TopBottom => 2. * frame_aspect,
+        };
+        self.horizontal = eye_aspect / self.aspect_ratio * self.horizontal;
+        self.aspect_ratio = eye_aspect;
+        self.update_viewport();
+        if let Some(lens) = &mut self.lens {
+            lens.prepare(self.focus_dist, self.aspect_ratio);
+        }
+
+        self.stereo = Some(stereo {
+            interocular,
+            convergence,
+            layout,
+        });
+        self
+    }
+
+    // Move the zero parallax distance of a stereo pair away from the focus distance
+    pub fn with_convergence(mut self, convergence: f64) -> camera {
+        if let Some(stereo) = &mut self.stereo {
+            stereo.convergence = convergence;
+        }
+        self
+    }
+
+    // Height of the film or sensor, 24 mm full frame by default with the scene in metres
+    pub fn with_film_height(mut self, film_height: f64) -> camera {
+        self.film_height = Some(film_height);
+        self
+    }
+
+    // Expose the image physically for a film speed and shutter time in seconds
+    pub fn with_exposure(mut self, iso: f64, shutter_time: f64) -> camera {
+        self.iso = Some(iso);
+        self.shutter_time = shutter_time;
+        self
+    }
+
+    // Brighten (positive) or darken (negative) the image by whole stops
+    pub fn with_ev_compensation(mut self, ev: f64) -> camera {
+        self.ev_compensation = ev;
+        self
+    }
+
+    // Set the aperture from an f-number. The thin lens gets the diameter for its focal length,
+    // a lens system has its aperture stop closed down.
+    pub fn with_f_number(mut self, f_number: f64) -> camera {
+        match self.lens.take() {
+            Some(lens) => {
+                let mut lens = lens.with_f_number(f_number);
+                lens.prepare(self.focus_dist, self.aspect_ratio);
+                self.lens = Some(lens);
+            }
+            None => {
+                self.lens_radius = self.focal_length() / (2. * f_number);
+                self.f_number = Some(f_number);
+            }
+        }
+        self
+    }
+
+    // Thin lens focal length in scene units, from the film height and field of view
+    pub fn focal_length(&self) -> f64 {
+        self.film_height.unwrap_or(DEFAULT_FILM_HEIGHT) / (2. * (self.vfov / 2.).tan())
+    }
+
+    // A lens system gives the f-number of its own aperture stop. The thin lens aperture is in
+    // scene units, so it only has one when set from an f-number or on a film of given height,
+    // and a pinhole has none at all.
+    pub fn f_number(&self) -> Option<f64> {
+        if let Some(lens) = &self.lens {
+            return lens.f_number();
+        }
+        if self.f_number.is_some() || self.lens_radius == 0. {
+            return self.f_number;
+        }
+        self.film_height
+            .map(|_| self.focal_length() / (2. * self.lens_radius))
+    }
+
+    // Exposure value at ISO 100 for the current settings, less any compensation. None without
+    // an f-number to meter.
+    pub fn ev100(&self) -> Option<f64> {
+        let iso = self.iso.unwrap_or(100.);
+        let n = self.f_number()?;
+        Some((n * n / self.shutter_time * 100. / iso).log2() - self.ev_compensation)
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> ray {
-        let rd = self.lens_radius * random_in_unit_disk();
-        let offset = self.u * rd.x + self.v * rd.y;
+    // Factor taking linear scene radiance to exposed film values before tone mapping. None
+    // when exposing for a film speed through an aperture without an f-number.
+    pub fn exposure_scale(&self) -> Option<f64> {
+        match self.iso {
+            Some(_) => Some(1. / (EXPOSURE_CALIBRATION * 2f64.powf(self.ev100()?))),
+            None => Some(2f64.powf(self.ev_compensation)),
+        }
+    }
+
+    // Trace rays through a real lens system instead of the thin lens, focused at the focus
+    // distance. The field of view and aperture then come from the lens prescription.
+    pub fn with_lens_system(mut self, mut lens: lens_system) -> camera {
+        lens.prepare(self.focus_dist, self.aspect_ratio);
+        self.lens = Some(lens);
+        self
+    }
 
+    // Ray through the image at (s, t), at a random time while the shutter is open.
+    // None when a lens system blocks the sample, which then carries no light.
+    pub fn get_ray(&self, s: f64, t: f64) -> Option<ray> {
+        let (s, t, eye) = self.split_eyes(s, t);
+        let mut r = match &self.lens {
+            Some(lens) => {
+                let (o, d) = lens.generate_ray(s, t)?;
+                self.lens_ray(o, d, eye)
+            }
+            None => self.project(s, t, eye)?,
+        };
+        r.time = self.time0 + rand::random::<f64>() * (self.time1 - self.time0);
+        Some(r)
+    }
+
+    // World ray for a lens system ray, whose camera space looks along +z
+    fn lens_ray(&self, o: vec3, d: vec3, eye: vec3) -> ray {
         ray::from(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
+            self.origin + eye + o.x * self.u + o.y * self.v - o.z * self.w,
+            d.x * self.u + d.y * self.v - d.z * self.w,
         )
     }
+
+    // Position within the image of one eye, and the offset of that eye from the origin
+    fn split_eyes(&self, s: f64, t: f64) -> (f64, f64, vec3) {
+        let stereo = match self.stereo {
+            Some(stereo) => stereo,
+            None => return (s, t, vec3::new()),
+        };
+        let (s, t, side) = match stereo.layout {
+            stereo_layout::SideBySide if s < 0.5 => (2. * s, t, -1.),
+            stereo_layout::SideBySide => (2. * s - 1., t, 1.),
+            stereo_layout::TopBottom if t >= 0.5 => (s, 2. * t - 1., -1.),
+            stereo_layout::TopBottom => (s, 2. * t, 1.),
+        };
+        (s, t, side * stereo.interocular / 2. * self.u)
+    }
+
+    // None when vignetting blocks the lens sample
+    fn project(&self, s: f64, t: f64, eye: vec3) -> Option<ray> {
+        let start = self.origin + eye;
+        match self.projection {
+            projection::Perspective => {
+                let mut target = self.lower_left_corner + s * self.horizontal + t * self.vertical;
+                if let Some(stereo) = self.stereo {
+                    // Off-axis stereo: both eyes aim at the same point at the convergence distance
+                    target =
+                        self.origin + stereo.convergence / self.focus_dist * (target - self.origin);
+                }
+                let focus = self.focus_point(start, target - start);
+                Some(self.through_lens(start, focus, self.lens_offset(s, t)?))
+            }
+            projection::Orthographic(height) => {
+                // Parallel rays from a window the size of the view, focused on the same plane
+                let width = self.aspect_ratio * height;
+                let start = start
+                    + (s - 0.5 + self.shift_x) * width * self.u
+                    + (t - 0.5 + self.shift_y) * height * self.v;
+                let focus = self.focus_point(start, -self.w);
+                Some(self.through_lens(start, focus, self.lens_offset(s, t)?))
+            }
+            projection::Fisheye => {
+                // Equidistant mapping, the angle off axis grows linearly with the image radius
+                let x = (2. * s - 1.) * self.aspect_ratio;
+                let y = 2. * t - 1.;
+                let theta = ((x * x + y * y).sqrt() * self.vfov / 2.).min(PI);
+                let phi = y.atan2(x);
+                let dir =
+                    theta.sin() * (phi.cos() * self.u + phi.sin() * self.v) - theta.cos() * self.w;
+                // Focus at the same distance along every ray, the field is too wide for a plane
+                let focus = start + self.focus_dist * unit_vector(dir);
+                Some(self.through_lens(start, focus, self.lens_offset(s, t)?))
+            }
+            projection::Equirectangular => {
+                // Longitude across the width and latitude up the height, no depth of field
+                let longitude = (s - 0.5) * 2. * PI;
+                let latitude = (t - 0.5) * PI;
+                let dir = latitude.cos() * (longitude.sin() * self.u - longitude.cos() * self.w)
+                    + latitude.sin() * self.v;
+
+                // ODS: the eyes sit on a circle, offset perpendicular to the direction of view,
+                // and come together toward the poles to avoid distortion looking up or down
+                let side = dot(eye, self.u);
+                let right = longitude.cos() * self.u + longitude.sin() * self.w;
+                Some(ray::from(self.origin + side * latitude.cos() * right, dir))
+            }
+        }
+    }
+
+    fn update_viewport(&mut self) {
+        self.lower_left_corner =
+            self.origin - self.horizontal / 2. - self.vertical / 2. - self.focus_dist * self.w
+                + self.shift_x * self.horizontal
+                + self.shift_y * self.vertical;
+    }
+
+    // Jitter the start over the lens while keeping the focus point sharp
+    fn through_lens(&self, start: point3, focus: point3, offset: vec3) -> ray {
+        ray::from(start + offset, focus - start - offset)
+    }
+
+    // Where a ray from `start` along `dir` meets the plane of focus
+    fn focus_point(&self, start: point3, dir: vec3) -> point3 {
+        let center = self.origin - self.focus_dist * self.w;
+        let denominator = dot(dir, self.focal_normal);
+        let t = dot(center - start, self.focal_normal) / denominator;
+        if denominator.abs() < 1e-9 || t <= 0. {
+            // The ray runs parallel to or away from a strongly tilted plane
+            return start + self.focus_dist * unit_vector(dir);
+        }
+        start + t * dir
+    }
+
+    // Offset from the lens center to a point on the aperture, for image position (s, t).
+    // None when the lens barrel blocks the sample.
+    fn lens_offset(&self, s: f64, t: f64) -> Option<vec3> {
+        if self.lens_radius == 0. {
+            return Some(vec3::new());
+        }
+
+        // The barrel shifts with the image position, reaching `cat_eye` in the corners
+        let x = (2. * s - 1.) * self.aspect_ratio;
+        let y = 2. * t - 1.;
+        let diagonal = (1. + self.aspect_ratio * self.aspect_ratio).sqrt();
+        let shift = self.cat_eye / diagonal * vec3::from(x, y, 0.);
+
+        let rd = self.lens_radius * self.aperture.sample_vignetted(shift)?;
+        Some(self.u * rd.x + self.v * rd.y)
+    }
 }
 
-fn degrees_to_radians(degrees: f64) -> f64 {
-    degrees * std::f64::consts::PI / 180.
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libconstant_medium::constant_medium;
+    use crate::libhittable::hittable;
+    use crate::libmaterial::{isotropic, lambertian, material};
+    use crate::libsphere::sphere;
+    use crate::libvec::color;
+
+    fn still() -> camera {
+        camera::from(
+            point3::from(13., 2., 3.),
+            point3::new(),
+            vec3::from(0., 1., 0.),
+            20.,
+            3. / 2.,
+            0.1,
+            10.,
+        )
+    }
+
+    // Pinhole looking down -z from z = 10 with a 90 degree vertical field and a 2:1 image,
+    // so u, v and w are the world axes
+    fn pinhole() -> camera {
+        camera::from(
+            point3::from(0., 0., 10.),
+            point3::new(),
+            vec3::from(0., 1., 0.),
+            90.,
+            2.,
+            0.,
+            10.,
+        )
+    }
+
+    fn assert_ray(cam: &camera, s: f64, t: f64, origin: point3, direction: vec3) {
+        let r = cam.project(s, t, vec3::new()).unwrap();
+        let d = unit_vector(r.direction) - unit_vector(direction);
+        assert!((r.origin - origin).length() < 1e-9, "origin at {} {}", s, t);
+        assert!(d.length() < 1e-9, "direction at {} {}", s, t);
+    }
+
+    #[test]
+    fn orthographic_rays_are_parallel_across_the_view() {
+        let cam = pinhole().with_projection(projection::Orthographic(4.));
+        let forward = vec3::from(0., 0., -1.);
+        assert_ray(&cam, 0.5, 0.5, point3::from(0., 0., 10.), forward);
+        assert_ray(&cam, 1., 0.5, point3::from(4., 0., 10.), forward);
+        assert_ray(&cam, 0., 0.5, point3::from(-4., 0., 10.), forward);
+        assert_ray(&cam, 0.5, 1., point3::from(0., 2., 10.), forward);
+        assert_ray(&cam, 0.5, 0., point3::from(0., -2., 10.), forward);
+    }
+
+    #[test]
+    fn fisheye_angle_grows_linearly_to_the_edges() {
+        let cam = pinhole().with_projection(projection::Fisheye);
+        let origin = point3::from(0., 0., 10.);
+        assert_ray(&cam, 0.5, 0.5, origin, vec3::from(0., 0., -1.));
+        // Half the vertical field at the top and bottom edges
+        assert_ray(&cam, 0.5, 1., origin, vec3::from(0., 1., -1.));
+        assert_ray(&cam, 0.5, 0., origin, vec3::from(0., -1., -1.));
+        // Twice as far out at the side edges of the 2:1 image, straight across
+        assert_ray(&cam, 1., 0.5, origin, vec3::from(1., 0., 0.));
+        assert_ray(&cam, 0., 0.5, origin, vec3::from(-1., 0., 0.));
+    }
+
+    #[test]
+    fn equirectangular_covers_the_whole_sphere() {
+        let cam = pinhole().with_projection(projection::Equirectangular);
+        let origin = point3::from(0., 0., 10.);
+        assert_ray(&cam, 0.5, 0.5, origin, vec3::from(0., 0., -1.));
+        assert_ray(&cam, 0.75, 0.5, origin, vec3::from(1., 0., 0.));
+        assert_ray(&cam, 0.25, 0.5, origin, vec3::from(-1., 0., 0.));
+        // Both side edges look straight behind
+        assert_ray(&cam, 1., 0.5, origin, vec3::from(0., 0., 1.));
+        assert_ray(&cam, 0., 0.5, origin, vec3::from(0., 0., 1.));
+        // The poles at the top and bottom edges
+        assert_ray(&cam, 0.5, 1., origin, vec3::from(0., 1., 0.));
+        assert_ray(&cam, 0.5, 0., origin, vec3::from(0., -1., 0.));
+    }
+
+    // Where the world x of a pinhole ray through (s, t) crosses the plane z = 0
+    fn x_at_z0(cam: &camera, s: f64, t: f64) -> f64 {
+        let r = cam.get_ray(s, t).unwrap();
+        let along = -r.origin.z / r.direction.z;
+        r.origin.x + along * r.direction.x
+    }
+
+    #[test]
+    fn shift_keeps_vertical_lines_parallel() {
+        let level = |lookat_y: f64| {
+            camera::from(
+                point3::from(0., 1., 10.),
+                point3::from(0., lookat_y, 0.),
+                vec3::from(0., 1., 0.),
+                40.,
+                3. / 2.,
+                0.,
+                10.,
+            )
+        };
+        // Shifting the view up sees the top of a tall building with the film still upright,
+        // so a column of the image stays on one vertical line in the world
+        let shifted = level(1.).with_shift(0., 0.3);
+        assert!((x_at_z0(&shifted, 0.9, 0.) - x_at_z0(&shifted, 0.9, 1.)).abs() < 1e-9);
+
+        // Pointing the camera up instead makes the verticals converge
+        let pointed = level(4.);
+        assert!((x_at_z0(&pointed, 0.9, 0.) - x_at_z0(&pointed, 0.9, 1.)).abs() > 0.1);
+    }
+
+    // Point where the rays through (s, t) from all over the lens meet
+    fn sharp_point(cam: &camera, s: f64, t: f64) -> point3 {
+        let a = cam.get_ray(s, t).unwrap();
+        let mut meeting = None;
+        let mut pairs = 0;
+        while pairs < 8 {
+            let b = cam.get_ray(s, t).unwrap();
+            if (b.origin - a.origin).length() < 0.2 * cam.lens_radius {
+                continue;
+            }
+            // Closest points of the two rays
+            let w0 = a.origin - b.origin;
+            let (aa, ab, bb) = (
+                dot(a.direction, a.direction),
+                dot(a.direction, b.direction),
+                dot(b.direction, b.direction),
+            );
+            let (d, e) = (dot(a.direction, w0), dot(b.direction, w0));
+            let denominator = aa * bb - ab * ab;
+            let pa = a.origin + (ab * e - bb * d) / denominator * a.direction;
+            let pb = b.origin + (aa * e - ab * d) / denominator * b.direction;
+            assert!((pa - pb).length() < 1e-6, "rays through {} {} miss", s, t);
+            if let Some(p) = meeting {
+                assert!((pa - p).length() < 1e-6);
+            }
+            meeting = Some(pa);
+            pairs += 1;
+        }
+        meeting.unwrap()
+    }
+
+    #[test]
+    fn tilted_focal_plane_is_sharp_at_different_depths() {
+        let cam = camera::from(
+            point3::new(),
+            point3::from(0., 0., -1.),
+            vec3::from(0., 1., 0.),
+            40.,
+            3. / 2.,
+            1.,
+            5.,
+        );
+        let depth = |cam: &camera, t: f64| -sharp_point(cam, 0.5, t).z;
+        assert!((depth(&cam, 0.1) - 5.).abs() < 1e-6);
+        assert!((depth(&cam, 0.9) - 5.).abs() < 1e-6);
+
+        // Tilted like a lens over a table: near the bottom of the image and far at the top
+        let tilted = cam.with_tilt(-30., 0.);
+        let (near, far) = (depth(&tilted, 0.1), depth(&tilted, 0.9));
+        assert!(far - near > 1., "sharp from {} to {}", near, far);
+    }
+
+    // Tangent of the angle off axis along u of the ray through (s, t)
+    fn horizontal_slope(cam: &camera, s: f64, t: f64) -> f64 {
+        let r = cam.get_ray(s, t).unwrap();
+        dot(r.direction, cam.u) / dot(r.direction, -cam.w)
+    }
+
+    #[test]
+    fn stereo_eyes_keep_their_own_aspect_ratio() {
+        let tan = (10f64).to_radians().tan();
+        let mut cam = still().with_stereo(0., 10., stereo_layout::SideBySide);
+        cam.lens_radius = 0.;
+        // The right edge of the right eye's half, each half being 3:4
+        assert!((horizontal_slope(&cam, 1., 0.5) - 0.75 * tan).abs() < 1e-9);
+
+        let cam = cam.with_stereo(0., 10., stereo_layout::TopBottom);
+        assert!((horizontal_slope(&cam, 1., 0.25) - 3. * tan).abs() < 1e-9);
+    }
+
+    #[test]
+    fn autofocus_looks_through_the_eye_of_the_stereo_half() {
+        let mat = crate::lambertian!(0.5, 0.5, 0.5);
+        let mut world = hittable_list::new();
+        world.add(hittable::Sphere(sphere::from(point3::new(), 1., &mat)));
+
+        // Without splitting the eyes the center of the left half would miss the sphere
+        let cam = camera::from(
+            point3::from(0., 0., 10.),
+            point3::new(),
+            vec3::from(0., 1., 0.),
+            20.,
+            3. / 2.,
+            0.1,
+            5.,
+        )
+        .with_stereo(0.06, 10., stereo_layout::SideBySide)
+        .with_autofocus(&world, 0.25, 0.5);
+        assert!((cam.focus_dist - 9.).abs() < 1e-3);
+        // and converges the eyes there
+        assert!((cam.stereo.unwrap().convergence - 9.).abs() < 1e-3);
+    }
+
+    #[test]
+    fn autofocus_looks_through_media() {
+        let mat = crate::lambertian!(0.5, 0.5, 0.5);
+        let smoke = crate::isotropic!(0.8, 0.8, 0.8);
+        let mut world = hittable_list::new();
+        world.add(crate::sphere!(0., 0., 0., 1., &mat));
+        world.add(crate::constant_medium!(
+            crate::sphere!(0., 0., 5., 1., &smoke),
+            100.,
+            &smoke
+        ));
+
+        // The smoke is dense enough that a medium hit would be almost certain
+        for _ in 0..10 {
+            let cam = camera::from(
+                point3::from(0., 0., 10.),
+                point3::new(),
+                vec3::from(0., 1., 0.),
+                20.,
+                3. / 2.,
+                0.1,
+                5.,
+            )
+            .with_autofocus(&world, 0.5, 0.5);
+            assert!((cam.focus_dist - 9.).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn f_number_of_the_thin_lens() {
+        // An aperture of 0.1 at 20 degrees is f/0.68 on full frame film
+        let cam = still();
+        assert_eq!(cam.f_number(), None);
+        let film = still().with_film_height(0.024);
+        assert!((film.f_number().unwrap() - 0.6805).abs() < 1e-3);
+        assert!((cam.with_f_number(8.).f_number().unwrap() - 8.).abs() < 1e-9);
+    }
+
+    #[test]
+    fn film_speed_needs_an_f_number_to_meter() {
+        let mut cam = still().with_film_height(0.024);
+        cam.lens_radius = 0.;
+        assert_eq!(cam.exposure_scale(), Some(1.));
+        assert_eq!(cam.with_exposure(100., 0.01).exposure_scale(), None);
+
+        // The sunny 16 rule: f/16 at 1/100 s on ISO 100 film
+        let sunny = still().with_f_number(16.).with_exposure(100., 0.01);
+        assert!((sunny.ev100().unwrap() - 14.64).abs() < 0.01);
+        assert!(sunny.exposure_scale().is_some());
+    }
 }
@@ -1,3 +1,5 @@
+use std::{fs::File, io, io::BufWriter};
+
 use crate::libvec::color;
 
 pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
@@ -11,6 +13,11 @@ pub fn clamp(x: f64, min: f64, max: f64) -> f64 {
 }
 
 pub fn write_color(pixel_color: color, samples_per_pixel: i32) -> String {
+    let [r, g, b] = to_rgb8(pixel_color, samples_per_pixel);
+    format!("{} {} {}", r, g, b)
+}
+
+pub fn to_rgb8(pixel_color: color, samples_per_pixel: i32) -> [u8; 3] {
     let mut r = pixel_color.x;
     let mut g = pixel_color.y;
     let mut b = pixel_color.z;
@@ -21,11 +28,45 @@ pub fn write_color(pixel_color: color, samples_per_pixel: i32) -> String {
     g = (scale * g).sqrt();
     b = (scale * b).sqrt();
 
-    // Write translated [0, 255] value of each color component
-    format!(
-        "{} {} {}",
-        (256. * clamp(r, 0., 0.999)) as i32,
-        (256. * clamp(g, 0., 0.999)) as i32,
-        (256. * clamp(b, 0., 0.999)) as i32
+    // Translate to [0, 255] value of each color component
+    [
+        (256. * clamp(r, 0., 0.999)) as u8,
+        (256. * clamp(g, 0., 0.999)) as u8,
+        (256. * clamp(b, 0., 0.999)) as u8,
+    ]
+}
+
+// Write pixel sums, top row first, as an 8-bit RGB PNG
+pub fn write_png(
+    path: &str,
+    width: i32,
+    height: i32,
+    pixels: &[color],
+    samples_per_pixel: i32,
+) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        width as u32,
+        height as u32,
+    );
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let data: Vec<u8> = pixels
+        .iter()
+        .flat_map(|&p| to_rgb8(p, samples_per_pixel))
+        .collect();
+    encoder
+        .write_header()
+        .and_then(|mut writer| writer.write_image_data(&data))
+        .map_err(io::Error::other)
+}
+
+pub fn xyz_to_rgb(x: f64, y: f64, z: f64) -> color {
+    // CIE XYZ to linear sRGB (D65 white point)
+    color::from(
+        3.2404542 * x - 1.5371385 * y - 0.4985314 * z,
+        -0.9692660 * x + 1.8760108 * y + 0.0415560 * z,
+        0.0556434 * x - 0.2040259 * y + 1.0572252 * z,
     )
 }
@@ -0,0 +1,155 @@
+use std::sync::Arc;
+
+use crate::{
+    libhittable::{hit_record, hittable},
+    libmaterial::material,
+    libray::ray,
+    libvec::vec3,
+};
+
+pub struct constant_medium {
+    pub boundary: Box<hittable>,
+    pub neg_inv_density: f64,
+    pub phase_function: Arc<material>,
+}
+
+impl constant_medium {
+    pub fn from(
+        boundary: hittable,
+        density: f64,
+        phase_function: &Arc<material>,
+    ) -> constant_medium {
+        constant_medium {
+            boundary: Box::new(boundary),
+            neg_inv_density: -1. / density,
+            phase_function: phase_function.clone(),
+        }
+    }
+
+    pub fn hit(&self, r: ray, t_min: f64, t_max: f64, rec: &mut hit_record) -> bool {
+        // Find both crossings of the boundary, so that rays starting inside still work
+        let mut rec1 = hit_record::new();
+        let mut rec2 = hit_record::new();
+
+        if !self
+            .boundary
+            .hit(r, -f64::INFINITY, f64::INFINITY, &mut rec1)
+        {
+            return false;
+        }
+        if !self
+            .boundary
+            .hit(r, rec1.t + 0.0001, f64::INFINITY, &mut rec2)
+        {
+            return false;
+        }
+
+        rec1.t = rec1.t.max(t_min);
+        rec2.t = rec2.t.min(t_max);
+        if rec1.t >= rec2.t {
+            return false;
+        }
+        rec1.t = rec1.t.max(0.);
+
+        // Scatter at an exponentially distributed distance inside the medium
+        let ray_length = r.direction.length();
+        let distance_inside_boundary = (rec2.t - rec1.t) * ray_length;
+        let hit_distance = self.neg_inv_density * rand::random::<f64>().ln();
+        if hit_distance > distance_inside_boundary {
+            return false;
+        }
+
+        rec.t = rec1.t + hit_distance / ray_length;
+        rec.p = r.at(rec.t);
+        rec.normal = vec3::from(1., 0., 0.); // arbitrary
+        rec.front_face = true; // also arbitrary
+        rec.mat = self.phase_function.clone();
+
+        true
+    }
+
+    pub fn transmittance(&self, r: ray, t_min: f64, t_max: f64) -> f64 {
+        let mut rec1 = hit_record::new();
+        let mut rec2 = hit_record::new();
+
+        if !self
+            .boundary
+            .hit(r, -f64::INFINITY, f64::INFINITY, &mut rec1)
+        {
+            return 1.;
+        }
+        if !self
+            .boundary
+            .hit(r, rec1.t + 0.0001, f64::INFINITY, &mut rec2)
+        {
+            return 1.;
+        }
+
+        let t0 = rec1.t.max(t_min).max(0.);
+        let t1 = rec2.t.min(t_max);
+        if t0 >= t1 {
+            return 1.;
+        }
+
+        // Beer-Lambert attenuation over the distance travelled inside
+        ((t1 - t0) * r.direction.length() / self.neg_inv_density).exp()
+    }
+}
+
+#[macro_export]
+macro_rules! constant_medium {
+    ($boundary:expr, $density:expr, $phase_function:expr) => {
+        hittable::ConstantMedium(constant_medium::from($boundary, $density, $phase_function))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libmaterial::isotropic;
+    use crate::libsphere::sphere;
+    use crate::libvec::{color, point3};
+
+    // Unit sphere of smoke with a density of 0.5 per unit length
+    fn smoke() -> constant_medium {
+        let phase = crate::isotropic!(0.8, 0.8, 0.8);
+        constant_medium::from(crate::sphere!(0., 0., 0., 1., &phase), 0.5, &phase)
+    }
+
+    #[test]
+    fn transmittance_is_exponential_in_the_distance_inside() {
+        let medium = smoke();
+        let expected = |distance: f64| (-0.5 * distance).exp();
+
+        // A direction of length 2 must not change the distance travelled
+        let through = ray::from(point3::from(0., 0., -5.), vec3::from(0., 0., 2.));
+        let tr = medium.transmittance(through, 0.001, f64::INFINITY);
+        assert!((tr - expected(2.)).abs() < 1e-6);
+
+        // Starting at the center, or stopping there
+        let from_center = ray::from(point3::new(), vec3::from(0., 0., 1.));
+        let tr = medium.transmittance(from_center, 0.001, f64::INFINITY);
+        assert!((tr - expected(1.)).abs() < 1e-3);
+        let tr = medium.transmittance(through, 0.001, 2.5);
+        assert!((tr - expected(1.)).abs() < 1e-6);
+
+        let miss = ray::from(point3::from(0., 2., -5.), vec3::from(0., 0., 1.));
+        assert_eq!(medium.transmittance(miss, 0.001, f64::INFINITY), 1.);
+    }
+
+    #[test]
+    fn free_flights_escape_as_often_as_the_transmittance() {
+        let medium = smoke();
+        let r = ray::from(point3::from(0., 0., -5.), vec3::from(0., 0., 1.));
+        let n = 100_000;
+        let escaped = (0..n)
+            .filter(|_| !medium.hit(r, 0.001, f64::INFINITY, &mut hit_record::new()))
+            .count();
+        let fraction = escaped as f64 / n as f64;
+        assert!(
+            (fraction - (-1f64).exp()).abs() < 0.01,
+            "{} escaped",
+            fraction
+        );
+    }
+}
@@ -0,0 +1,153 @@
+use crate::libvec::color;
+
+// Sub-rectangle of the image in pixels counted from the top left, x0 and y0 inclusive
+// and x1 and y1 exclusive
+#[derive(Clone, Copy)]
+pub struct crop_window {
+    pub x0: i32,
+    pub y0: i32,
+    pub x1: i32,
+    pub y1: i32,
+}
+
+impl crop_window {
+    pub fn full(width: i32, height: i32) -> crop_window {
+        crop_window {
+            x0: 0,
+            y0: 0,
+            x1: width,
+            y1: height,
+        }
+    }
+
+    // Clamped to the image, None when nothing is left
+    pub fn from_pixels(
+        x0: i32,
+        y0: i32,
+        x1: i32,
+        y1: i32,
+        width: i32,
+        height: i32,
+    ) -> Option<crop_window> {
+        let window = crop_window {
+            x0: x0.clamp(0, width),
+            y0: y0.clamp(0, height),
+            x1: x1.clamp(0, width),
+            y1: y1.clamp(0, height),
+        };
+        if window.width() <= 0 || window.height() <= 0 {
+            return None;
+        }
+        Some(window)
+    }
+
+    // Window given in fractions of the image size, covering every pixel it touches
+    pub fn from_normalized(
+        x0: f64,
+        y0: f64,
+        x1: f64,
+        y1: f64,
+        width: i32,
+        height: i32,
+    ) -> Option<crop_window> {
+        crop_window::from_pixels(
+            (x0 * width as f64).floor() as i32,
+            (y0 * height as f64).floor() as i32,
+            (x1 * width as f64).ceil() as i32,
+            (y1 * height as f64).ceil() as i32,
+            width,
+            height,
+        )
+    }
+
+    // Parse `x0,y0,x1,y1`, in whole pixels or in fractions of the image size
+    pub fn parse(arg: &str, normalized: bool, width: i32, height: i32) -> Option<crop_window> {
+        let values: Vec<&str> = arg.split(',').map(|v| v.trim()).collect();
+        if values.len() != 4 {
+            return None;
+        }
+        if normalized {
+            let v = values
+                .iter()
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .ok()?;
+            crop_window::from_normalized(v[0], v[1], v[2], v[3], width, height)
+        } else {
+            let v = values
+                .iter()
+                .map(|v| v.parse::<i32>())
+                .collect::<Result<Vec<i32>, _>>()
+                .ok()?;
+            crop_window::from_pixels(v[0], v[1], v[2], v[3], width, height)
+        }
+    }
+
+    pub fn width(&self) -> i32 {
+        self.x1 - self.x0
+    }
+
+    pub fn height(&self) -> i32 {
+        self.y1 - self.y0
+    }
+
+    // Place the pixels of the window, top row first, into a black frame of the full size
+    pub fn composite(&self, pixels: &[color], width: i32, height: i32) -> Vec<color> {
+        let mut frame = vec![color::new(); (width * height) as usize];
+        for (k, pixel) in pixels.iter().enumerate() {
+            let x = self.x0 + k as i32 % self.width();
+            let y = self.y0 + k as i32 / self.width();
+            frame[(y * width + x) as usize] = *pixel;
+        }
+        frame
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corners(w: crop_window) -> (i32, i32, i32, i32) {
+        (w.x0, w.y0, w.x1, w.y1)
+    }
+
+    #[test]
+    fn parses_whole_pixels_only() {
+        let w = crop_window::parse("10, 20,110,70", false, 200, 100).unwrap();
+        assert_eq!(corners(w), (10, 20, 110, 70));
+        assert!(crop_window::parse("10.7,20,110,70", false, 200, 100).is_none());
+        assert!(crop_window::parse("10,20,110", false, 200, 100).is_none());
+        assert!(crop_window::parse("10,20,110,70,5", false, 200, 100).is_none());
+
+        // Clamped to the image, and nothing left is an error
+        let w = crop_window::parse("-5,90,500,120", false, 200, 100).unwrap();
+        assert_eq!(corners(w), (0, 90, 200, 100));
+        assert!(crop_window::parse("50,50,50,80", false, 200, 100).is_none());
+    }
+
+    #[test]
+    fn normalized_windows_cover_every_pixel_they_touch() {
+        let w = crop_window::parse("0.25,0.5,0.5,1", true, 200, 100).unwrap();
+        assert_eq!(corners(w), (50, 50, 100, 100));
+        let w = crop_window::from_normalized(0.101, 0.109, 0.201, 0.2, 200, 100).unwrap();
+        assert_eq!(corners(w), (20, 10, 41, 20));
+    }
+
+    #[test]
+    fn composite_places_the_window_in_a_black_frame() {
+        let w = crop_window::from_pixels(1, 2, 3, 4, 4, 5).unwrap();
+        let pixels: Vec<color> = (1..=4).map(|k| color::from(k as f64, 0., 0.)).collect();
+        let frame = w.composite(&pixels, 4, 5);
+        assert_eq!(frame.len(), 20);
+        for (k, pixel) in frame.iter().enumerate() {
+            let expected = match k {
+                9 => 1.,
+                10 => 2.,
+                13 => 3.,
+                14 => 4.,
+                _ => 0.,
+            };
+            assert_eq!(pixel.x, expected);
+        }
+    }
+}
@@ -0,0 +1,388 @@
+use std::{
+    io::{self, BufRead, BufReader, Read},
+    sync::Arc,
+};
+
+use crate::{
+    libhittable::{hit_record, hittable},
+    libmaterial::material,
+    libperlin::perlin,
+    libray::ray,
+    libvec::{point3, vec3},
+};
+
+pub enum density_field {
+    Grid(density_grid),
+    Noise(noise_density),
+}
+
+impl density_field {
+    // Density in [0, max()] at a world space point
+    pub fn value(&self, p: point3) -> f64 {
+        match self {
+            density_field::Grid(g) => g.value(p),
+            density_field::Noise(n) => n.value(p),
+        }
+    }
+
+    pub fn max(&self) -> f64 {
+        match self {
+            density_field::Grid(g) => g.max,
+            density_field::Noise(_) => 1.,
+        }
+    }
+}
+
+pub struct density_grid {
+    nx: usize,
+    ny: usize,
+    nz: usize,
+    data: Vec<f32>,
+    max: f64,
+    min_corner: point3,
+    max_corner: point3,
+}
+
+impl density_grid {
+    pub fn from(
+        nx: usize,
+        ny: usize,
+        nz: usize,
+        data: Vec<f32>,
+        min_corner: point3,
+        max_corner: point3,
+    ) -> density_grid {
+        assert!(nx > 0 && ny > 0 && nz > 0, "empty grid");
+        assert_eq!(data.len(), nx * ny * nz, "grid size does not match data");
+        assert!(
+            data.iter().all(|d| d.is_finite()),
+            "grid densities must be finite"
+        );
+        let max = data.iter().fold(0., |m: f64, &d| m.max(d as f64));
+        density_grid {
+            nx,
+            ny,
+            nz,
+            data,
+            max,
+            min_corner,
+            max_corner,
+        }
+    }
+
+    // Load a raw grid: an ASCII header line "nx ny nz" followed by nx*ny*nz
+    // little-endian f32 values with x varying fastest, then y, then z
+    pub fn load(path: &str, min_corner: point3, max_corner: point3) -> io::Result<density_grid> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut reader = BufReader::new(std::fs::File::open(path)?);
+
+        let mut header = String::new();
+        reader.read_line(&mut header)?;
+        let dims: Vec<usize> = header
+            .split_whitespace()
+            .map(|s| s.parse::<usize>())
+            .collect::<Result<_, _>>()
+            .map_err(|e| invalid(e.to_string()))?;
+        if dims.len() != 3 {
+            return Err(invalid("expected header \"nx ny nz\"".to_string()));
+        }
+        if dims.contains(&0) {
+            return Err(invalid(format!(
+                "grid dimensions {}x{}x{} must all be at least 1",
+                dims[0], dims[1], dims[2]
+            )));
+        }
+
+        let count = dims[0]
+            .checked_mul(dims[1])
+            .and_then(|n| n.checked_mul(dims[2]))
+            .filter(|n| n.checked_mul(4).is_some())
+            .ok_or_else(|| invalid("grid dimensions too large".to_string()))?;
+        let mut bytes = Vec::new();
+        reader.read_to_end(&mut bytes)?;
+        if bytes.len() != count * 4 {
+            return Err(invalid(format!(
+                "expected {} values after the header, found {} bytes",
+                count,
+                bytes.len()
+            )));
+        }
+        let mut data: Vec<f32> = bytes
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        if let Some(i) = data.iter().position(|d| !d.is_finite()) {
+            return Err(invalid(format!("density {} is not finite", i)));
+        }
+        for d in &mut data {
+            *d = d.max(0.);
+        }
+
+        Ok(density_grid::from(
+            dims[0], dims[1], dims[2], data, min_corner, max_corner,
+        ))
+    }
+
+    fn at(&self, i: usize, j: usize, k: usize) -> f64 {
+        self.data[(k * self.ny + j) * self.nx + i] as f64
+    }
+
+    pub fn value(&self, p: point3) -> f64 {
+        // Trilinear interpolation between the voxels around p
+        let extent = self.max_corner - self.min_corner;
+        let gx = (p.x - self.min_corner.x) / extent.x * (self.nx - 1) as f64;
+        let gy = (p.y - self.min_corner.y) / extent.y * (self.ny - 1) as f64;
+        let gz = (p.z - self.min_corner.z) / extent.z * (self.nz - 1) as f64;
+        if !(gx >= 0. && gy >= 0. && gz >= 0.)
+            || gx > (self.nx - 1) as f64
+            || gy > (self.ny - 1) as f64
+            || gz > (self.nz - 1) as f64
+        {
+            return 0.;
+        }
+
+        let i = (gx as usize).min(self.nx.saturating_sub(2));
+        let j = (gy as usize).min(self.ny.saturating_sub(2));
+        let k = (gz as usize).min(self.nz.saturating_sub(2));
+        let (fx, fy, fz) = (gx - i as f64, gy - j as f64, gz - k as f64);
+        let i1 = (i + 1).min(self.nx - 1);
+        let j1 = (j + 1).min(self.ny - 1);
+        let k1 = (k + 1).min(self.nz - 1);
+
+        let lerp = |a: f64, b: f64, t: f64| a + (b - a) * t;
+        let d00 = lerp(self.at(i, j, k), self.at(i1, j, k), fx);
+        let d10 = lerp(self.at(i, j1, k), self.at(i1, j1, k), fx);
+        let d01 = lerp(self.at(i, j, k1), self.at(i1, j, k1), fx);
+        let d11 = lerp(self.at(i, j1, k1), self.at(i1, j1, k1), fx);
+        lerp(lerp(d00, d10, fy), lerp(d01, d11, fy), fz)
+    }
+}
+
+pub struct noise_density {
+    noise: perlin,
+    frequency: f64,
+    octaves: i32,
+}
+
+impl noise_density {
+    pub fn from(frequency: f64, octaves: i32) -> noise_density {
+        noise_density {
+            noise: perlin::new(),
+            frequency,
+            octaves,
+        }
+    }
+
+    pub fn value(&self, p: point3) -> f64 {
+        self.noise
+            .turb(self.frequency * p, self.octaves)
+            .clamp(0., 1.)
+    }
+}
+
+pub struct heterogeneous_medium {
+    pub boundary: Box<hittable>,
+    pub field: density_field,
+    pub density: f64,
+    pub phase_function: Arc<material>,
+}
+
+impl heterogeneous_medium {
+    pub fn from(
+        boundary: hittable,
+        field: density_field,
+        density: f64,
+        phase_function: &Arc<material>,
+    ) -> heterogeneous_medium {
+        heterogeneous_medium {
+            boundary: Box::new(boundary),
+            field,
+            density,
+            phase_function: phase_function.clone(),
+        }
+    }
+
+    fn sigma_t(&self, p: point3) -> f64 {
+        self.density * self.field.value(p)
+    }
+
+    // Parametric interval of the ray inside the boundary, clipped to [t_min, t_max]
+    fn interval(&self, r: ray, t_min: f64, t_max: f64) -> Option<(f64, f64)> {
+        let mut rec1 = hit_record::new();
+        let mut rec2 = hit_record::new();
+
+        if !self
+            .boundary
+            .hit(r, -f64::INFINITY, f64::INFINITY, &mut rec1)
+        {
+            return None;
+        }
+        if !self
+            .boundary
+            .hit(r, rec1.t + 0.0001, f64::INFINITY, &mut rec2)
+        {
+            return None;
+        }
+
+        let t0 = rec1.t.max(t_min).max(0.);
+        let t1 = rec2.t.min(t_max);
+        if t0 >= t1 {
+            return None;
+        }
+        Some((t0, t1))
+    }
+
+    pub fn hit(&self, r: ray, t_min: f64, t_max: f64, rec: &mut hit_record) -> bool {
+        let (t0, t1) = match self.interval(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return false,
+        };
+
+        let sigma_max = self.density * self.field.max();
+        if sigma_max <= 0. {
+            return false;
+        }
+
+        // Delta tracking: sample against the majorant and accept real collisions
+        let ray_length = r.direction.length();
+        let mut t = t0;
+        loop {
+            t -= (1. - rand::random::<f64>()).ln() / sigma_max / ray_length;
+            if t >= t1 {
+                return false;
+            }
+            if rand::random::<f64>() * sigma_max < self.sigma_t(r.at(t)) {
+                break;
+            }
+        }
+
+        rec.t = t;
+        rec.p = r.at(t);
+        rec.normal = vec3::from(1., 0., 0.); // arbitrary
+        rec.front_face = true; // also arbitrary
+        rec.mat = self.phase_function.clone();
+
+        true
+    }
+
+    pub fn transmittance(&self, r: ray, t_min: f64, t_max: f64) -> f64 {
+        let (t0, t1) = match self.interval(r, t_min, t_max) {
+            Some(interval) => interval,
+            None => return 1.,
+        };
+
+        let sigma_max = self.density * self.field.max();
+        if sigma_max <= 0. {
+            return 1.;
+        }
+
+        // Ratio tracking: weight by the null-collision probability at every tentative collision
+        let ray_length = r.direction.length();
+        let mut t = t0;
+        let mut tr = 1.;
+        loop {
+            t -= (1. - rand::random::<f64>()).ln() / sigma_max / ray_length;
+            if t >= t1 {
+                return tr;
+            }
+            tr *= 1. - self.sigma_t(r.at(t)) / sigma_max;
+        }
+    }
+}
+
+#[macro_export]
+macro_rules! heterogeneous_medium {
+    ($boundary:expr, $field:expr, $density:expr, $phase_function:expr) => {
+        hittable::HeterogeneousMedium(heterogeneous_medium::from(
+            $boundary,
+            $field,
+            $density,
+            $phase_function,
+        ))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn corners() -> (point3, point3) {
+        (point3::from(-1., 0., 2.), point3::from(1., 4., 3.))
+    }
+
+    // 3x2x2 grid whose value is the index of the sample
+    fn counting_grid() -> density_grid {
+        let (min_corner, max_corner) = corners();
+        density_grid::from(
+            3,
+            2,
+            2,
+            (0..12).map(|i| i as f32).collect(),
+            min_corner,
+            max_corner,
+        )
+    }
+
+    #[test]
+    fn grid_interpolates_between_its_samples() {
+        let grid = counting_grid();
+        for k in 0..2 {
+            for j in 0..2 {
+                for i in 0..3 {
+                    let p = point3::from(-1. + i as f64, 4. * j as f64, 2. + k as f64);
+                    let expected = ((k * 2 + j) * 3 + i) as f64;
+                    assert!(
+                        (grid.value(p) - expected).abs() < 1e-9,
+                        "at {} {} {}",
+                        i,
+                        j,
+                        k
+                    );
+                }
+            }
+        }
+        // Halfway along each axis, the mean of the corners around it
+        assert!((grid.value(point3::from(-0.5, 2., 2.5)) - 5.).abs() < 1e-9);
+        assert_eq!(grid.value(point3::from(1.01, 2., 2.5)), 0.);
+        assert_eq!(grid.value(point3::from(0., -0.01, 2.5)), 0.);
+    }
+
+    #[test]
+    fn load_reads_the_header_and_checks_the_size() {
+        let path = std::env::temp_dir().join(format!("riow_grid_{}.raw", std::process::id()));
+        let path = path.to_str().unwrap();
+        let (min_corner, max_corner) = corners();
+        let write = |header: &str, values: &[f32]| {
+            let mut bytes = header.as_bytes().to_vec();
+            for v in values {
+                bytes.extend_from_slice(&v.to_le_bytes());
+            }
+            std::fs::write(path, bytes).unwrap();
+        };
+
+        let values: Vec<f32> = (0..12).map(|i| i as f32).collect();
+        write("3 2 2\n", &values);
+        let grid = density_grid::load(path, min_corner, max_corner).unwrap();
+        let expected = counting_grid();
+        for p in [point3::from(0., 1., 2.2), point3::from(0.7, 3.9, 2.9)] {
+            assert!((grid.value(p) - expected.value(p)).abs() < 1e-9);
+        }
+
+        write("3 2 2\n", &values[..11]);
+        assert!(density_grid::load(path, min_corner, max_corner).is_err());
+        write("3 0 2\n", &[]);
+        assert!(density_grid::load(path, min_corner, max_corner).is_err());
+        write("3 2\n", &values[..6]);
+        assert!(density_grid::load(path, min_corner, max_corner).is_err());
+        for bad in [f32::INFINITY, f32::NAN] {
+            let mut values = values.clone();
+            values[5] = bad;
+            write("3 2 2\n", &values);
+            let err = density_grid::load(path, min_corner, max_corner)
+                .err()
+                .unwrap();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -1,34 +1,126 @@
 use std::sync::Arc;
 
 use crate::{
+    libaabb::aabb,
+    libbvh::bvh_node,
+    libconstant_medium::constant_medium,
+    libheterogeneous_medium::heterogeneous_medium,
     libmaterial::{lambertian, material},
+    libmoving_sphere::moving_sphere,
     libray::ray,
     libsphere::sphere,
+    libtransform::transform,
     libvec::{color, dot, point3, vec3},
 };
 
+// Offset past a cut-out hit before looking for the next surface
+pub const CUTOUT_EPSILON: f64 = 1e-4;
+
 pub enum hittable {
     Sphere(sphere),
+    MovingSphere(moving_sphere),
+    ConstantMedium(constant_medium),
+    HeterogeneousMedium(heterogeneous_medium),
+    Transform(transform),
+    Bvh(bvh_node),
 }
 
 impl hittable {
-    pub fn hit(&self, r: ray, t_min: f64, t_max: f64, rec: &mut hit_record) -> bool {
+    pub fn hit<'a>(&'a self, r: ray, t_min: f64, t_max: f64, rec: &mut hit_record<'a>) -> bool {
+        match self {
+            hittable::Sphere(s) => s.hit(r, t_min, t_max, rec),
+            hittable::MovingSphere(s) => s.hit(r, t_min, t_max, rec),
+            hittable::ConstantMedium(m) => m.hit(r, t_min, t_max, rec),
+            hittable::HeterogeneousMedium(m) => m.hit(r, t_min, t_max, rec),
+            hittable::Transform(t) => t.hit(r, t_min, t_max, rec),
+            hittable::Bvh(b) => b.hit(r, t_min, t_max, rec),
+        }
+    }
+
+    // Like `hit`, also recording this object as the one hit. A BVH records its own leaves.
+    pub fn hit_object<'a>(
+        &'a self,
+        r: ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut hit_record<'a>,
+    ) -> bool {
+        if !self.hit(r, t_min, t_max, rec) {
+            return false;
+        }
+        if !matches!(self, hittable::Bvh(_)) {
+            rec.object = Some(self);
+        }
+        true
+    }
+
+    // Like `hit` for surfaces only, passing through participating media whose hits are
+    // random. Probes that must find the same surface every time, such as autofocus, use it.
+    pub fn hit_surface<'a>(
+        &'a self,
+        r: ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut hit_record<'a>,
+    ) -> bool {
         match self {
             hittable::Sphere(s) => s.hit(r, t_min, t_max, rec),
+            hittable::MovingSphere(s) => s.hit(r, t_min, t_max, rec),
+            hittable::ConstantMedium(_) | hittable::HeterogeneousMedium(_) => false,
+            hittable::Transform(t) => t.hit_surface(r, t_min, t_max, rec),
+            hittable::Bvh(b) => b.hit_surface(r, t_min, t_max, rec),
+        }
+    }
+
+    // Fraction of light passing along the ray between t_min and t_max
+    pub fn transmittance(&self, r: ray, t_min: f64, t_max: f64) -> f64 {
+        match self {
+            hittable::Sphere(_) | hittable::MovingSphere(_) => {
+                // Partially opaque surfaces let a share of the light through
+                let mut tr = 1.;
+                let mut t_min = t_min;
+                let mut rec = hit_record::new();
+                while tr > 0. && self.hit(r, t_min, t_max, &mut rec) {
+                    tr *= 1. - rec.mat.opacity(&rec);
+                    t_min = rec.t + CUTOUT_EPSILON;
+                }
+                tr
+            }
+            hittable::ConstantMedium(m) => m.transmittance(r, t_min, t_max),
+            hittable::HeterogeneousMedium(m) => m.transmittance(r, t_min, t_max),
+            hittable::Transform(t) => t.transmittance(r, t_min, t_max),
+            hittable::Bvh(b) => b.transmittance(r, t_min, t_max),
+        }
+    }
+
+    // Box enclosing the object over the whole interval from time0 to time1
+    pub fn bounding_box(&self, time0: f64, time1: f64) -> Option<aabb> {
+        match self {
+            hittable::Sphere(s) => Some(s.bounding_box()),
+            hittable::MovingSphere(s) => Some(s.bounding_box(time0, time1)),
+            hittable::ConstantMedium(m) => m.boundary.bounding_box(time0, time1),
+            hittable::HeterogeneousMedium(m) => m.boundary.bounding_box(time0, time1),
+            hittable::Transform(t) => t.bounding_box(time0, time1),
+            hittable::Bvh(b) => Some(b.bounding_box()),
         }
     }
 }
 
 #[derive(Clone)]
-pub struct hit_record {
+pub struct hit_record<'a> {
     pub p: point3,
     pub normal: vec3,
     pub t: f64,
     pub mat: Arc<material>,
     pub front_face: bool,
+    pub u: f64,
+    pub v: f64,
+    pub dpdu: vec3, // surface tangents along u and v, used for normal and bump mapping
+    pub dpdv: vec3,
+    pub object: Option<&'a hittable>, // outermost object hit, for materials tracing their own shape
 }
 
-impl hit_record {
+impl<'a> hit_record<'a> {
     pub fn set_face_normal(&mut self, r: ray, outward_normal: vec3) {
         self.front_face = dot(r.direction, outward_normal) < 0.;
         self.normal = if self.front_face {
@@ -38,39 +130,107 @@ impl hit_record {
         };
     }
 
-    pub fn new() -> hit_record {
+    pub fn new() -> hit_record<'a> {
         hit_record {
             p: point3::new(),
             normal: vec3::new(),
             t: 0.,
             mat: Arc::new(material::Lambertian(lambertian::from(color::new()))),
             front_face: false,
+            u: 0.,
+            v: 0.,
+            dpdu: vec3::new(),
+            dpdv: vec3::new(),
+            object: None,
+        }
+    }
+}
+
+// Outcome of sampling a material
+pub struct scatter_record<'a> {
+    pub attenuation: color,
+    pub scattered: ray,
+    pub is_specular: bool, // sampled from a delta lobe, which light sampling cannot reach
+    pub exit: Option<hit_record<'a>>, // where the scattered ray leaves when it moved below the surface
+}
+
+impl<'a> scatter_record<'a> {
+    pub fn new() -> scatter_record<'a> {
+        scatter_record {
+            attenuation: color::new(),
+            scattered: ray::new(),
+            is_specular: false,
+            exit: None,
         }
     }
 }
 
+// Attenuation and BSDF values are sampled at the ray's wavelengths in spectral mode
 pub trait scatter {
-    fn scatter(
-        &self,
-        r_in: &ray,
-        rec: &hit_record,
-        attenuation: &mut color,
-        scattered: &mut ray,
-    ) -> bool;
+    fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool;
+
+    // BSDF times cosine for light arriving along wi, used for light sampling. Delta lobes
+    // are left out, their light is found by following the scattered ray.
+    fn eval(&self, r_in: &ray, rec: &hit_record, wi: vec3) -> color;
+
+    // Apply normal or bump maps to the shading normal before scattering
+    fn perturb_normal(&self, rec: &mut hit_record);
+
+    // Fraction of light blocked by the surface, below 1 for alpha-masked materials
+    fn opacity(&self, rec: &hit_record) -> f64;
+
+    // Whether a ray passes through the surface at this hit
+    fn is_cut_out(&self, rec: &hit_record) -> bool;
 }
 
 impl scatter for Arc<material> {
-    fn scatter(
-        &self,
-        r_in: &ray,
-        rec: &hit_record,
-        attenuation: &mut color,
-        scattered: &mut ray,
-    ) -> bool {
+    fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
+        match self.as_ref() {
+            material::Lambertian(l) => l.scatter(r_in, rec, srec),
+            material::Metal(m) => m.scatter(r_in, rec, srec),
+            material::Dielectric(d) => d.scatter(r_in, rec, srec),
+            material::Isotropic(i) => i.scatter(r_in, rec, srec),
+            material::HenyeyGreenstein(h) => h.scatter(r_in, rec, srec),
+            material::Principled(p) => p.scatter(r_in, rec, srec),
+            material::Layered(l) => l.scatter(r_in, rec, srec),
+            material::Mapped(m) => m.scatter(r_in, rec, srec),
+            material::Mix(m) => m.scatter(r_in, rec, srec),
+            material::Subsurface(s) => s.scatter(r_in, rec, srec),
+        }
+    }
+
+    fn eval(&self, r_in: &ray, rec: &hit_record, wi: vec3) -> color {
+        match self.as_ref() {
+            material::Lambertian(l) => l.eval(r_in, rec, wi),
+            material::Metal(m) => m.eval(r_in, rec, wi),
+            material::Dielectric(d) => d.eval(r_in, rec, wi),
+            material::Isotropic(i) => i.eval(r_in, rec, wi),
+            material::HenyeyGreenstein(h) => h.eval(r_in, rec, wi),
+            material::Principled(p) => p.eval(r_in, rec, wi),
+            material::Layered(l) => l.eval(r_in, rec, wi),
+            material::Mapped(m) => m.eval(r_in, rec, wi),
+            material::Mix(m) => m.eval(r_in, rec, wi),
+            material::Subsurface(s) => s.eval(r_in, rec, wi),
+        }
+    }
+
+    fn perturb_normal(&self, rec: &mut hit_record) {
+        if let material::Mapped(m) = self.as_ref() {
+            m.perturb_normal(rec);
+        }
+    }
+
+    fn opacity(&self, rec: &hit_record) -> f64 {
+        match self.as_ref() {
+            material::Mapped(m) => m.opacity(rec),
+            _ => 1.,
+        }
+    }
+
+    fn is_cut_out(&self, rec: &hit_record) -> bool {
         match self.as_ref() {
-            material::Lambertian(l) => l.scatter(r_in, rec, attenuation, scattered),
-            material::Metal(m) => m.scatter(r_in, rec, attenuation, scattered),
-            material::Dielectric(d) => d.scatter(r_in, rec, attenuation, scattered),
+            material::Mapped(m) => m.is_cut_out(rec),
+            _ => false,
         }
     }
 }
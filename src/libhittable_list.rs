@@ -1,40 +1,177 @@
 use crate::{
-    libhittable::{hit_record, hittable},
+    libbvh::bvh_node,
+    libhittable::{hit_record, hittable, scatter, CUTOUT_EPSILON},
+    liblight::light,
     libray::ray,
+    libsky::sky,
 };
 
 pub struct hittable_list {
     pub objects: Vec<hittable>,
+    pub lights: Vec<light>,
+    pub sky: Option<sky>,
 }
 
 impl hittable_list {
     pub fn new() -> hittable_list {
         hittable_list {
             objects: Vec::new(),
+            lights: Vec::new(),
+            sky: None,
         }
     }
 
-    pub fn clear(mut self) {
-        self.objects.clear();
-    }
-
     pub fn add(&mut self, object: hittable) {
         self.objects.push(object);
     }
 
-    pub fn hit(&self, r: ray, t_min: f64, t_max: f64, rec: &mut hit_record) -> bool {
+    pub fn add_light(&mut self, light: light) {
+        self.lights.push(light);
+    }
+
+    // Use a physical sky as background and sample its sun as a light
+    pub fn set_sky(&mut self, sky: sky) {
+        self.lights.push(sky.sun());
+        self.sky = Some(sky);
+    }
+
+    pub fn hit<'a>(&'a self, r: ray, t_min: f64, t_max: f64, rec: &mut hit_record<'a>) -> bool {
         let temp_rec = hit_record::new();
         let mut hit_anything = false;
         let mut closest_so_far = t_max;
 
         for object in &self.objects {
             let mut temp_rec = temp_rec.clone();
-            if object.hit(r, t_min, closest_so_far, &mut temp_rec) {
+            let mut t_near = t_min;
+            while object.hit_object(r, t_near, closest_so_far, &mut temp_rec) {
+                // Keep looking behind surfaces cut out by an opacity mask
+                if temp_rec.mat.is_cut_out(&temp_rec) {
+                    t_near = temp_rec.t + CUTOUT_EPSILON;
+                    continue;
+                }
                 hit_anything = true;
                 closest_so_far = temp_rec.clone().t;
                 *rec = temp_rec;
+                break;
             }
         }
         hit_anything
     }
+
+    // Closest surface along the ray, passing through media and through surfaces less than
+    // half opaque, so that probing the same ray always finds the same surface
+    pub fn hit_surface<'a>(
+        &'a self,
+        r: ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut hit_record<'a>,
+    ) -> bool {
+        let temp_rec = hit_record::new();
+        let mut hit_anything = false;
+        let mut closest_so_far = t_max;
+
+        for object in &self.objects {
+            let mut temp_rec = temp_rec.clone();
+            let mut t_near = t_min;
+            while object.hit_surface(r, t_near, closest_so_far, &mut temp_rec) {
+                if temp_rec.mat.opacity(&temp_rec) < 0.5 {
+                    t_near = temp_rec.t + CUTOUT_EPSILON;
+                    continue;
+                }
+                hit_anything = true;
+                closest_so_far = temp_rec.t;
+                *rec = temp_rec;
+                break;
+            }
+        }
+        hit_anything
+    }
+
+    // Gather the objects with a bounding box over time0 to time1 into a BVH, leaving the
+    // others to be tested one by one
+    pub fn build_bvh(&mut self, time0: f64, time1: f64) {
+        let (bounded, unbounded): (Vec<hittable>, Vec<hittable>) = self
+            .objects
+            .drain(..)
+            .partition(|object| object.bounding_box(time0, time1).is_some());
+        self.objects = unbounded;
+        if !bounded.is_empty() {
+            self.objects.push(bvh_node::from(bounded, time0, time1));
+        }
+    }
+
+    pub fn transmittance(&self, r: ray, t_min: f64, t_max: f64) -> f64 {
+        let mut tr = 1.;
+        for object in &self.objects {
+            tr *= object.transmittance(r, t_min, t_max);
+            if tr <= 0. {
+                return 0.;
+            }
+        }
+        tr
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libmapped::{alpha_mode, mapped};
+    use crate::libmaterial::{lambertian, material};
+    use crate::libsphere::sphere;
+    use crate::libtexture::texture;
+    use crate::libvec::{color, point3, vec3};
+    use std::sync::Arc;
+
+    // A sphere at the origin with the given opacity in front of an opaque one further down -z
+    fn spheres_in_a_row(opacity: f64, mode: alpha_mode) -> hittable_list {
+        let diffuse: Arc<material> = crate::lambertian!(0.5, 0.5, 0.5);
+        let alpha = Arc::new(texture::Solid(color::from(opacity, opacity, opacity)));
+        let masked: Arc<material> = Arc::new(material::Mapped(
+            mapped::from(&diffuse).with_opacity(&alpha, mode),
+        ));
+        let mut world = hittable_list::new();
+        world.add(crate::sphere!(0., 0., 0., 1., &masked));
+        world.add(crate::sphere!(0., 0., -5., 1., &diffuse));
+        world
+    }
+
+    fn down_the_row() -> ray {
+        ray::from(point3::from(0., 0., 5.), vec3::from(0., 0., -1.))
+    }
+
+    #[test]
+    fn hit_steps_past_cut_out_surfaces() {
+        for bvh in [false, true] {
+            let mut world = spheres_in_a_row(0.2, alpha_mode::Threshold(0.5));
+            if bvh {
+                world.build_bvh(0., 1.);
+            }
+            // Through both sides of the cut out sphere onto the one behind it
+            let mut rec = hit_record::new();
+            assert!(world.hit(down_the_row(), 0.001, f64::INFINITY, &mut rec));
+            assert!(
+                (rec.t - 9.).abs() < 1e-9,
+                "hit at {} with bvh {}",
+                rec.t,
+                bvh
+            );
+
+            let opaque = spheres_in_a_row(0.8, alpha_mode::Threshold(0.5));
+            assert!(opaque.hit(down_the_row(), 0.001, f64::INFINITY, &mut rec));
+            assert!((rec.t - 4.).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn transmittance_honours_opacity() {
+        // Light crosses both sides of the masked sphere, stopping short of the opaque one
+        let world = spheres_in_a_row(0.25, alpha_mode::Stochastic);
+        let tr = world.transmittance(down_the_row(), 0.001, 8.);
+        assert!((tr - 0.75 * 0.75).abs() < 1e-9);
+
+        let cut = spheres_in_a_row(0.2, alpha_mode::Threshold(0.5));
+        assert_eq!(cut.transmittance(down_the_row(), 0.001, 8.), 1.);
+        assert_eq!(cut.transmittance(down_the_row(), 0.001, f64::INFINITY), 0.);
+    }
 }
@@ -0,0 +1,255 @@
+use std::sync::Arc;
+
+use crate::{
+    libhittable::{hit_record, scatter, scatter_record},
+    libmaterial::{material, SMOOTH_ALPHA},
+    libmicrofacet::{
+        fresnel_dielectric, ggx_d, ggx_g1, ggx_g2, reflect_local, refract_local, sample_ggx_vndf,
+    },
+    libray::ray,
+    libspectrum::spectral,
+    libvec::{color, dot, frame, unit_vector, vec3},
+};
+
+// Maximum number of bounces between the base and the underside of the coat
+const MAX_INTERNAL_BOUNCES: i32 = 16;
+
+// A dielectric coat over an arbitrary base material. Light bouncing between the base and
+// the coat is followed stochastically, using the mean surface for the internal interface.
+pub struct layered {
+    base: Arc<material>,
+    ior: f64,
+    alpha: f64,
+    absorption: color, // absorption coefficient inside the coat
+    thickness: f64,
+}
+
+impl layered {
+    pub fn from(base: &Arc<material>, ior: f64, roughness: f64) -> layered {
+        // Maps only act at the top of a material, so they go around the coat instead
+        assert!(
+            !base.has_map(),
+            "layered base cannot be mapped, map the layered material instead"
+        );
+        let roughness = roughness.clamp(0., 1.);
+        layered {
+            base: base.clone(),
+            ior,
+            alpha: roughness * roughness,
+            absorption: color::new(),
+            thickness: 0.,
+        }
+    }
+
+    // Tint the coat, e.g. for colored lacquer
+    pub fn with_absorption(mut self, absorption: color, thickness: f64) -> layered {
+        self.absorption = absorption;
+        self.thickness = thickness;
+        self
+    }
+
+    fn coat_transmittance(&self, cos_theta: f64, lambda: vec3) -> color {
+        let distance = self.thickness / cos_theta.abs().max(1e-4);
+        let absorption = spectral(self.absorption, lambda);
+        color::from(
+            (-absorption.x * distance).exp(),
+            (-absorption.y * distance).exp(),
+            (-absorption.z * distance).exp(),
+        )
+    }
+
+    pub fn has_map(&self) -> bool {
+        self.base.has_map()
+    }
+
+    pub fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
+        if !rec.front_face {
+            return self.base.scatter(r_in, rec, srec);
+        }
+
+        let shading = frame::from_normal(rec.normal);
+        let wo = shading.to_local(-unit_vector(r_in.direction));
+        if wo.z <= 0. {
+            return false;
+        }
+
+        // Top interface: reflect off the coat or refract into it
+        let smooth = self.alpha < SMOOTH_ALPHA;
+        let m = if smooth {
+            vec3::from(0., 0., 1.)
+        } else {
+            sample_ggx_vndf(wo, self.alpha)
+        };
+        let mut throughput = color::from(1., 1., 1.);
+
+        if rand::random::<f64>() < fresnel_dielectric(dot(wo, m), self.ior) {
+            let wi = reflect_local(wo, m);
+            if wi.z <= 0. {
+                return false;
+            }
+            if !smooth {
+                throughput *= ggx_g2(wo, wi, self.alpha) / ggx_g1(wo, self.alpha);
+            }
+            srec.attenuation = throughput;
+            srec.scattered = r_in.spawn(rec.p, shading.to_world(wi));
+            srec.is_specular = smooth;
+            return true;
+        }
+
+        let mut w = match refract_local(wo, m, self.ior) {
+            Some(w) if w.z < 0. => w,
+            _ => return false,
+        };
+        if !smooth {
+            throughput *= ggx_g2(wo, w, self.alpha) / ggx_g1(wo, self.alpha);
+        }
+
+        // Random walk between the base and the underside of the coat. The path stays a delta
+        // path only through a smooth coat and specular base lobes.
+        let down = vec3::from(0., 0., -1.);
+        let mut specular = smooth;
+        for _ in 0..MAX_INTERNAL_BOUNCES {
+            throughput = throughput * self.coat_transmittance(w.z, r_in.lambda);
+
+            let mut base = scatter_record::new();
+            let incoming = r_in.spawn(rec.p, shading.to_world(w));
+            if !self.base.scatter(&incoming, rec, &mut base) {
+                return false;
+            }
+            throughput = throughput * base.attenuation;
+            specular &= base.is_specular;
+            w = unit_vector(shading.to_local(base.scattered.direction));
+            if w.z <= 0. {
+                return false;
+            }
+
+            throughput = throughput * self.coat_transmittance(w.z, r_in.lambda);
+
+            // Leave through the coat or get reflected back down to the base
+            if rand::random::<f64>() < fresnel_dielectric(w.z, 1. / self.ior) {
+                w = vec3::from(w.x, w.y, -w.z);
+                continue;
+            }
+            let wi = match refract_local(-w, down, 1. / self.ior) {
+                Some(wi) => wi,
+                None => return false,
+            };
+
+            srec.attenuation = throughput;
+            srec.scattered = r_in.spawn(rec.p, shading.to_world(wi));
+            srec.is_specular = specular;
+            return true;
+        }
+        false
+    }
+
+    pub fn eval(&self, r_in: &ray, rec: &hit_record, wi: vec3) -> color {
+        if !rec.front_face {
+            return self.base.eval(r_in, rec, wi);
+        }
+
+        let shading = frame::from_normal(rec.normal);
+        let wo = shading.to_local(-unit_vector(r_in.direction));
+        let wi = shading.to_local(wi);
+        if wo.z <= 0. || wi.z <= 0. {
+            return color::new();
+        }
+
+        // Glossy reflection off a rough coat
+        let mut value = color::new();
+        if self.alpha >= SMOOTH_ALPHA {
+            let h = unit_vector(wo + wi);
+            let f = fresnel_dielectric(dot(wo, h), self.ior);
+            let spec = f * ggx_d(h, self.alpha) * ggx_g2(wo, wi, self.alpha) / (4. * wo.z);
+            value = color::from(spec, spec, spec);
+        }
+
+        // Base lobe seen through a smooth coat. Light refracted into the coat is squeezed
+        // into a narrower cone, which the cos_i / (eta^2 cos_t) Jacobian accounts for.
+        let up = vec3::from(0., 0., 1.);
+        let (to, ti) = match (
+            refract_local(wo, up, self.ior),
+            refract_local(wi, up, self.ior),
+        ) {
+            (Some(to), Some(ti)) => (to, ti),
+            _ => return value,
+        };
+        let incoming = r_in.spawn(rec.p, shading.to_world(to));
+        let base = self.base.eval(&incoming, rec, shading.to_world(-ti));
+        let transmission =
+            (1. - fresnel_dielectric(wo.z, self.ior)) * (1. - fresnel_dielectric(wi.z, self.ior));
+        let jacobian = wi.z / (self.ior * self.ior * -ti.z);
+
+        value
+            + transmission
+                * jacobian
+                * base
+                * self.coat_transmittance(to.z, r_in.lambda)
+                * self.coat_transmittance(ti.z, r_in.lambda)
+                * self.internal_bounces(r_in, rec)
+    }
+
+    // Gain from light the underside of the coat reflects back down to the base, summed
+    // as for a diffuse base with the base's albedo at normal incidence. Exact for a
+    // Lambertian base, which is what the random walk in scatter converges to.
+    fn internal_bounces(&self, r_in: &ray, rec: &hit_record) -> color {
+        let down = r_in.spawn(rec.p, -rec.normal);
+        let albedo = std::f64::consts::PI * self.base.eval(&down, rec, rec.normal);
+
+        // Cosine weighted mean of the coat's internal reflectance over a round trip
+        let steps = 16;
+        let mut reflectance = color::new();
+        for k in 0..steps {
+            let cos_theta = (k as f64 + 0.5) / steps as f64;
+            let tr = self.coat_transmittance(cos_theta, r_in.lambda);
+            reflectance += fresnel_dielectric(cos_theta, 1. / self.ior)
+                * (2. * cos_theta / steps as f64)
+                * tr
+                * tr;
+        }
+
+        let gain = |a: f64, r: f64| 1. / (1. - a.clamp(0., 1.) * r);
+        color::from(
+            gain(albedo.x, reflectance.x),
+            gain(albedo.y, reflectance.y),
+            gain(albedo.z, reflectance.z),
+        )
+    }
+}
+
+#[macro_export]
+macro_rules! layered {
+    ($base:expr, $ior:expr, $roughness:expr) => {
+        std::sync::Arc::new(material::Layered(layered::from($base, $ior, $roughness)))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libmaterial::{lambertian, tests::albedos};
+
+    #[test]
+    fn coated_diffuse_eval_matches_scatter() {
+        for albedo in [1., 0.5] {
+            let base = crate::lambertian!(albedo, albedo, albedo);
+            let clear = crate::layered!(&base, 1.5, 0.);
+            let tinted = Arc::new(material::Layered(
+                layered::from(&base, 1.5, 0.).with_absorption(color::from(1., 1., 1.), 0.3),
+            ));
+            for cos_theta in [0.3, 0.7, 1.] {
+                for mat in [&clear, &tinted] {
+                    let (sampled, integrated) = albedos(mat, cos_theta);
+                    assert!(
+                        (sampled - integrated).abs() < 0.02,
+                        "sampled {} but eval integrates to {} at {} over albedo {}",
+                        sampled,
+                        integrated,
+                        cos_theta,
+                        albedo
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,615 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use crate::{
+    libmicrofacet::refract_local,
+    libvec::{dot, unit_vector, vec3},
+};
+
+// Radial slices of the film for which exit pupil bounds are precomputed
+const EXIT_PUPIL_SLICES: usize = 64;
+// Rays traced toward the rear element per slice when bounding the exit pupil
+const EXIT_PUPIL_SAMPLES: usize = 16384;
+// Bisection steps when measuring the entrance pupil
+const ENTRANCE_PUPIL_STEPS: i32 = 48;
+
+// One spherical interface of the lens, listed from the scene side toward the film
+#[derive(Clone, Copy, PartialEq)]
+pub struct lens_element {
+    pub curvature_radius: f64, // zero for the aperture stop
+    pub thickness: f64,        // distance to the next interface toward the film
+    pub eta: f64,              // IOR of the medium behind the interface, zero for air
+    pub aperture_radius: f64,
+}
+
+#[derive(Clone, Copy)]
+struct bounds2 {
+    min_x: f64,
+    min_y: f64,
+    max_x: f64,
+    max_y: f64,
+}
+
+impl bounds2 {
+    fn empty() -> bounds2 {
+        bounds2 {
+            min_x: f64::INFINITY,
+            min_y: f64::INFINITY,
+            max_x: f64::NEG_INFINITY,
+            max_y: f64::NEG_INFINITY,
+        }
+    }
+
+    fn square(half_width: f64) -> bounds2 {
+        bounds2 {
+            min_x: -half_width,
+            min_y: -half_width,
+            max_x: half_width,
+            max_y: half_width,
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.min_x > self.max_x || self.min_y > self.max_y
+    }
+
+    fn inside(&self, x: f64, y: f64) -> bool {
+        x >= self.min_x && x <= self.max_x && y >= self.min_y && y <= self.max_y
+    }
+
+    fn add(&mut self, x: f64, y: f64) {
+        self.min_x = self.min_x.min(x);
+        self.min_y = self.min_y.min(y);
+        self.max_x = self.max_x.max(x);
+        self.max_y = self.max_y.max(y);
+    }
+
+    fn area(&self) -> f64 {
+        (self.max_x - self.min_x) * (self.max_y - self.min_y)
+    }
+}
+
+// Exit pupil bounds along with the elements they were traced for. Clones of a lens share it,
+// so each frame and each refocus only traces the pupil again when the elements moved.
+type pupil_cache = Arc<Mutex<Option<(Vec<lens_element>, Vec<bounds2>)>>>;
+
+// Thick lens system traced element by element, after Kolb et al. (1995) and pbrt. In lens
+// space the film sits at z = 0 and the elements extend toward negative z.
+#[derive(Clone)]
+pub struct lens_system {
+    elements: Vec<lens_element>,
+    film_diagonal: f64,
+    film_width: f64,
+    film_height: f64,
+    exit_pupil_bounds: Vec<bounds2>,
+    max_pupil_area: f64,
+    pupil_cache: pupil_cache,
+}
+
+impl lens_system {
+    // Fails without elements, the film always needs a rear element to look through
+    pub fn from(elements: Vec<lens_element>, film_diagonal: f64) -> io::Result<lens_system> {
+        if elements.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "no lens elements",
+            ));
+        }
+        Ok(lens_system {
+            elements,
+            film_diagonal,
+            film_width: 0.,
+            film_height: 0.,
+            exit_pupil_bounds: Vec::new(),
+            max_pupil_area: 0.,
+            pupil_cache: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    // Load a tabular prescription with one element per line: curvature radius, thickness,
+    // IOR and aperture diameter, in millimetres. Lines starting with '#' are comments.
+    // `scale` converts millimetres to scene units and the film diagonal is in millimetres.
+    pub fn load(path: &str, scale: f64, film_diagonal: f64) -> io::Result<lens_system> {
+        let invalid = |msg: String| io::Error::new(io::ErrorKind::InvalidData, msg);
+        let mut elements = Vec::new();
+
+        for (number, line) in std::fs::read_to_string(path)?.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let values = line
+                .split_whitespace()
+                .map(|v| v.parse::<f64>())
+                .collect::<Result<Vec<f64>, _>>()
+                .map_err(|_| invalid(format!("line {}: expected numbers", number + 1)))?;
+            if values.len() != 4 {
+                return Err(invalid(format!(
+                    "line {}: expected 4 columns, found {}",
+                    number + 1,
+                    values.len()
+                )));
+            }
+            elements.push(lens_element {
+                curvature_radius: values[0] * scale,
+                thickness: values[1] * scale,
+                eta: values[2],
+                aperture_radius: values[3] * scale / 2.,
+            });
+        }
+
+        lens_system::from(elements, film_diagonal * scale)
+    }
+
+    // Open or close the aperture stop, in the same units as the prescription was scaled to
+    pub fn with_stop_diameter(mut self, diameter: f64) -> lens_system {
+        for element in &mut self.elements {
+            if element.curvature_radius == 0. {
+                element.aperture_radius = (diameter / 2.).min(element.aperture_radius);
+            }
+        }
+        self
+    }
+
+    // Focus at `focus_dist` in front of the film and precompute the exit pupil, unless it was
+    // already traced for this focus and stop
+    pub fn prepare(&mut self, focus_dist: f64, aspect_ratio: f64) {
+        let diagonal = (1. + aspect_ratio * aspect_ratio).sqrt();
+        self.film_width = self.film_diagonal * aspect_ratio / diagonal;
+        self.film_height = self.film_diagonal / diagonal;
+
+        if let Some(thickness) = self.focus_thick_lens(focus_dist) {
+            self.elements.last_mut().unwrap().thickness = thickness;
+        }
+
+        let cache = self.pupil_cache.clone();
+        let mut cache = cache.lock().unwrap();
+        self.exit_pupil_bounds = match cache.as_ref() {
+            Some((elements, bounds)) if *elements == self.elements => bounds.clone(),
+            _ => {
+                let half_diagonal = self.film_diagonal / 2.;
+                let bounds: Vec<bounds2> = (0..EXIT_PUPIL_SLICES)
+                    .map(|i| {
+                        let r0 = i as f64 / EXIT_PUPIL_SLICES as f64 * half_diagonal;
+                        let r1 = (i + 1) as f64 / EXIT_PUPIL_SLICES as f64 * half_diagonal;
+                        self.bound_exit_pupil(r0, r1)
+                    })
+                    .collect();
+                *cache = Some((self.elements.clone(), bounds.clone()));
+                bounds
+            }
+        };
+        self.max_pupil_area = self
+            .exit_pupil_bounds
+            .iter()
+            .map(|b| b.area())
+            .fold(0., f64::max);
+    }
+
+    fn rear_z(&self) -> f64 {
+        self.elements.last().unwrap().thickness
+    }
+
+    fn front_z(&self) -> f64 {
+        self.elements.iter().map(|e| e.thickness).sum()
+    }
+
+    fn rear_radius(&self) -> f64 {
+        self.elements.last().unwrap().aperture_radius
+    }
+
+    // Point on the film for image position (s, t)
+    fn film_point(&self, s: f64, t: f64) -> vec3 {
+        // The lens flips the image, so the top right of the picture is the bottom left of the film
+        vec3::from(
+            -(s - 0.5) * self.film_width,
+            -(t - 0.5) * self.film_height,
+            0.,
+        )
+    }
+
+    // Ray from the film at image position (s, t) toward the scene, in camera space with
+    // the view along +z. None when the lens system blocks the sample.
+    pub fn generate_ray(&self, s: f64, t: f64) -> Option<(vec3, vec3)> {
+        let origin = self.film_point(s, t);
+        let (rear_x, rear_y, area) = self.sample_exit_pupil(origin.x, origin.y);
+        let direction = vec3::from(rear_x, rear_y, self.rear_z()) - origin;
+
+        // Keep samples with probability proportional to the irradiance they carry, giving
+        // natural vignetting from the cos^4 falloff and the size of the exit pupil
+        let cos_theta = unit_vector(direction).z;
+        let weight = cos_theta.powi(4) * area / self.max_pupil_area;
+        if rand::random::<f64>() >= weight {
+            return None;
+        }
+
+        self.trace_from_film(origin, direction)
+    }
+
+    // Ray from the film at (s, t) through the middle of its exit pupil bounds, or through the
+    // center of the rear element when that is blocked. None when both are.
+    pub fn chief_ray(&self, s: f64, t: f64) -> Option<(vec3, vec3)> {
+        let origin = self.film_point(s, t);
+        let (bounds, sin_theta, cos_theta) = self.exit_pupil(origin.x, origin.y);
+        let x = 0.5 * (bounds.min_x + bounds.max_x);
+        let y = 0.5 * (bounds.min_y + bounds.max_y);
+        [
+            vec3::from(
+                cos_theta * x - sin_theta * y,
+                sin_theta * x + cos_theta * y,
+                0.,
+            ),
+            vec3::new(),
+        ]
+        .into_iter()
+        .find_map(|rear| {
+            let target = vec3::from(rear.x, rear.y, self.rear_z());
+            self.trace_from_film(origin, target - origin)
+        })
+    }
+
+    // Exit pupil bounds for a film point, computed along +x, and the sine and cosine of the
+    // angle they are rotated by to reach the point
+    fn exit_pupil(&self, film_x: f64, film_y: f64) -> (bounds2, f64, f64) {
+        let r_film = (film_x * film_x + film_y * film_y).sqrt();
+        let index = ((r_film / (self.film_diagonal / 2.) * EXIT_PUPIL_SLICES as f64) as usize)
+            .min(EXIT_PUPIL_SLICES - 1);
+        let (sin_theta, cos_theta) = if r_film > 0. {
+            (film_y / r_film, film_x / r_film)
+        } else {
+            (0., 1.)
+        };
+        (self.exit_pupil_bounds[index], sin_theta, cos_theta)
+    }
+
+    fn sample_exit_pupil(&self, film_x: f64, film_y: f64) -> (f64, f64, f64) {
+        let (bounds, sin_theta, cos_theta) = self.exit_pupil(film_x, film_y);
+        let x = bounds.min_x + rand::random::<f64>() * (bounds.max_x - bounds.min_x);
+        let y = bounds.min_y + rand::random::<f64>() * (bounds.max_y - bounds.min_y);
+        (
+            cos_theta * x - sin_theta * y,
+            sin_theta * x + cos_theta * y,
+            bounds.area(),
+        )
+    }
+
+    fn bound_exit_pupil(&self, r0: f64, r1: f64) -> bounds2 {
+        let rear_bounds = bounds2::square(1.5 * self.rear_radius());
+        let mut pupil = bounds2::empty();
+
+        for i in 0..EXIT_PUPIL_SAMPLES {
+            let film_x = r0 + (i as f64 + 0.5) / EXIT_PUPIL_SAMPLES as f64 * (r1 - r0);
+            let x =
+                rear_bounds.min_x + rand::random::<f64>() * (rear_bounds.max_x - rear_bounds.min_x);
+            let y =
+                rear_bounds.min_y + rand::random::<f64>() * (rear_bounds.max_y - rear_bounds.min_y);
+
+            let origin = vec3::from(film_x, 0., 0.);
+            let direction = vec3::from(x, y, self.rear_z()) - origin;
+            if pupil.inside(x, y) || self.trace_from_film(origin, direction).is_some() {
+                pupil.add(x, y);
+            }
+        }
+
+        if pupil.is_empty() {
+            return rear_bounds;
+        }
+
+        // Grow by twice the diagonal of the sampled region over the square root of the sample
+        // count, as pbrt does, to cover what the samples missed
+        let diagonal = 2. * 1.5 * self.rear_radius() * std::f64::consts::SQRT_2;
+        let expansion = 2. * diagonal / (EXIT_PUPIL_SAMPLES as f64).sqrt();
+        pupil.min_x -= expansion;
+        pupil.min_y -= expansion;
+        pupil.max_x += expansion;
+        pupil.max_y += expansion;
+        pupil
+    }
+
+    // Trace a camera space ray from the film out through the front element
+    fn trace_from_film(&self, origin: vec3, direction: vec3) -> Option<(vec3, vec3)> {
+        let mut o = vec3::from(origin.x, origin.y, -origin.z);
+        let mut d = vec3::from(direction.x, direction.y, -direction.z);
+        let mut element_z = 0.;
+
+        for i in (0..self.elements.len()).rev() {
+            let element = self.elements[i];
+            element_z -= element.thickness;
+            let (o_hit, normal) = self.intersect(element, element_z, o, d)?;
+            o = o_hit;
+
+            if let Some(n) = normal {
+                let eta_i = air_or(element.eta);
+                let eta_t = if i > 0 {
+                    air_or(self.elements[i - 1].eta)
+                } else {
+                    1.
+                };
+                d = refract_local(-unit_vector(d), n, eta_t / eta_i)?;
+            }
+        }
+        Some((vec3::from(o.x, o.y, -o.z), vec3::from(d.x, d.y, -d.z)))
+    }
+
+    // Trace a camera space ray from the scene in through the front element to the film side
+    fn trace_from_scene(&self, origin: vec3, direction: vec3) -> Option<(vec3, vec3)> {
+        let mut o = vec3::from(origin.x, origin.y, -origin.z);
+        let mut d = vec3::from(direction.x, direction.y, -direction.z);
+        let mut element_z = -self.front_z();
+
+        for i in 0..self.elements.len() {
+            let element = self.elements[i];
+            let (o_hit, normal) = self.intersect(element, element_z, o, d)?;
+            o = o_hit;
+
+            if let Some(n) = normal {
+                let eta_i = if i > 0 {
+                    air_or(self.elements[i - 1].eta)
+                } else {
+                    1.
+                };
+                let eta_t = air_or(element.eta);
+                d = refract_local(-unit_vector(d), n, eta_t / eta_i)?;
+            }
+            element_z += element.thickness;
+        }
+        Some((vec3::from(o.x, o.y, -o.z), vec3::from(d.x, d.y, -d.z)))
+    }
+
+    // Hit point on an element in lens space, with the normal facing the incoming ray unless
+    // the element is the aperture stop
+    fn intersect(
+        &self,
+        element: lens_element,
+        element_z: f64,
+        o: vec3,
+        d: vec3,
+    ) -> Option<(vec3, Option<vec3>)> {
+        let radius = element.curvature_radius;
+        let (t, normal) = if radius == 0. {
+            ((element_z - o.z) / d.z, None)
+        } else {
+            let oc = o - vec3::from(0., 0., element_z + radius);
+            let a = d.length_squared();
+            let b = 2. * dot(d, oc);
+            let c = oc.length_squared() - radius * radius;
+            let discriminant = b * b - 4. * a * c;
+            if discriminant < 0. {
+                return None;
+            }
+            let root = discriminant.sqrt();
+            let t0 = (-b - root) / (2. * a);
+            let t1 = (-b + root) / (2. * a);
+
+            // Which root lies on the element depends on the direction and the curvature
+            let t = if (d.z > 0.) ^ (radius < 0.) { t0 } else { t1 };
+            if t < 0. {
+                return None;
+            }
+            let n = unit_vector(oc + t * d);
+            (t, Some(if dot(n, -d) < 0. { -n } else { n }))
+        };
+
+        if !t.is_finite() {
+            return None;
+        }
+        let p = o + t * d;
+        if p.x * p.x + p.y * p.y > element.aperture_radius * element.aperture_radius {
+            return None;
+        }
+        Some((p, normal))
+    }
+
+    // Effective focal length of the system, from a ray entering parallel to the axis
+    pub fn focal_length(&self) -> Option<f64> {
+        let (pz0, fz0) = self.front_cardinal_points()?;
+        Some((fz0 - pz0).abs())
+    }
+
+    // Radius of the entrance pupil, the widest ray parallel to the axis to reach the film
+    fn entrance_pupil_radius(&self) -> f64 {
+        let passes = |x: f64| {
+            self.trace_from_scene(
+                vec3::from(x, 0., self.front_z() + 1.),
+                vec3::from(0., 0., -1.),
+            )
+            .is_some()
+        };
+        let (mut inside, mut outside) = (0., self.elements[0].aperture_radius);
+        for _ in 0..ENTRANCE_PUPIL_STEPS {
+            let x = 0.5 * (inside + outside);
+            if passes(x) {
+                inside = x;
+            } else {
+                outside = x;
+            }
+        }
+        inside
+    }
+
+    // Working f-number at infinity focus, from the focal length and entrance pupil
+    pub fn f_number(&self) -> Option<f64> {
+        let radius = self.entrance_pupil_radius();
+        if radius <= 0. {
+            return None;
+        }
+        Some(self.focal_length()? / (2. * radius))
+    }
+
+    // Close the aperture stop down to an f-number. The entrance pupil is an image of the stop
+    // and scales with it; the stop cannot open past its size in the prescription.
+    pub fn with_f_number(self, f_number: f64) -> lens_system {
+        let stop = match self.elements.iter().find(|e| e.curvature_radius == 0.) {
+            Some(stop) => stop.aperture_radius,
+            None => return self,
+        };
+        match self.f_number() {
+            Some(current) => self.with_stop_diameter(2. * stop * current / f_number),
+            None => self,
+        }
+    }
+
+    // Principal plane and focal point along z for light entering from the scene
+    fn front_cardinal_points(&self) -> Option<(f64, f64)> {
+        let x = 0.001 * self.film_diagonal;
+        let scene_in = (
+            vec3::from(x, 0., self.front_z() + 1.),
+            vec3::from(0., 0., -1.),
+        );
+        let film_out = self.trace_from_scene(scene_in.0, scene_in.1)?;
+        Some(cardinal_points(scene_in, film_out))
+    }
+
+    // Rear element thickness that brings `focus_dist` into focus, from the thick lens
+    // approximation of the system
+    fn focus_thick_lens(&self, focus_dist: f64) -> Option<f64> {
+        let x = 0.001 * self.film_diagonal;
+
+        // Cardinal points from parallel rays entering from either side
+        let (pz0, fz0) = self.front_cardinal_points()?;
+
+        let film_in = (
+            vec3::from(x, 0., self.rear_z() - 1.),
+            vec3::from(0., 0., 1.),
+        );
+        let scene_out = self.trace_from_film(film_in.0, film_in.1)?;
+        let (pz1, _) = cardinal_points(film_in, scene_out);
+
+        let f = fz0 - pz0;
+        let z = -focus_dist;
+        let c = (pz1 - z - pz0) * (pz1 - z - 4. * f - pz0);
+        if c <= 0. {
+            return None;
+        }
+        let delta = 0.5 * (pz1 - z + pz0 - c.sqrt());
+        Some(self.rear_z() + delta)
+    }
+}
+
+fn air_or(eta: f64) -> f64 {
+    if eta == 0. {
+        1.
+    } else {
+        eta
+    }
+}
+
+// Principal plane and focal point along z for a ray parallel to the axis
+fn cardinal_points(r_in: (vec3, vec3), r_out: (vec3, vec3)) -> (f64, f64) {
+    let (o_in, _) = r_in;
+    let (o_out, d_out) = r_out;
+    let tf = -o_out.x / d_out.x;
+    let tp = (o_in.x - o_out.x) / d_out.x;
+    (-(o_out.z + tp * d_out.z), -(o_out.z + tf * d_out.z))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doublet() -> lens_system {
+        let element = |curvature_radius, thickness, eta, aperture_radius| lens_element {
+            curvature_radius,
+            thickness,
+            eta,
+            aperture_radius,
+        };
+        let mut lens = lens_system::from(
+            vec![
+                element(50., 5., 1.5, 10.),
+                element(-50., 2., 0., 10.),
+                element(0., 45., 0., 4.),
+            ],
+            35.,
+        )
+        .unwrap();
+        lens.prepare(1000., 1.5);
+        lens
+    }
+
+    #[test]
+    fn f_number_follows_the_stop() {
+        // Thick biconvex lens of 50.17 mm focal length behind a stop of 10 mm
+        let mut lens = lens_system::from(
+            vec![
+                lens_element {
+                    curvature_radius: 0.,
+                    thickness: 1.,
+                    eta: 0.,
+                    aperture_radius: 5.,
+                },
+                lens_element {
+                    curvature_radius: 50.,
+                    thickness: 1.,
+                    eta: 1.5,
+                    aperture_radius: 20.,
+                },
+                lens_element {
+                    curvature_radius: -50.,
+                    thickness: 50.,
+                    eta: 0.,
+                    aperture_radius: 20.,
+                },
+            ],
+            35.,
+        )
+        .unwrap();
+        assert!((lens.focal_length().unwrap() - 50.167).abs() < 0.01);
+        assert!((lens.f_number().unwrap() - 5.017).abs() < 0.01);
+
+        lens = lens.with_f_number(8.);
+        assert!((lens.f_number().unwrap() - 8.).abs() < 0.01);
+    }
+
+    #[test]
+    fn rejects_an_empty_prescription() {
+        assert!(lens_system::from(Vec::new(), 35.).is_err());
+    }
+
+    #[test]
+    fn exit_pupil_bounds_hold_every_ray_through_the_lens() {
+        let lens = doublet();
+        let half_diagonal = lens.film_diagonal / 2.;
+        let rear = bounds2::square(1.5 * lens.rear_radius());
+        let mut passed = 0;
+        for (i, bounds) in lens.exit_pupil_bounds.iter().enumerate() {
+            for _ in 0..2000 {
+                let film_x =
+                    (i as f64 + rand::random::<f64>()) / EXIT_PUPIL_SLICES as f64 * half_diagonal;
+                let x = rear.min_x + rand::random::<f64>() * (rear.max_x - rear.min_x);
+                let y = rear.min_y + rand::random::<f64>() * (rear.max_y - rear.min_y);
+                let origin = vec3::from(film_x, 0., 0.);
+                let direction = vec3::from(x, y, lens.rear_z()) - origin;
+                if lens.trace_from_film(origin, direction).is_some() {
+                    assert!(bounds.inside(x, y));
+                    passed += 1;
+                }
+            }
+        }
+        assert!(passed > 0);
+    }
+
+    #[test]
+    fn exit_pupil_is_traced_again_only_when_the_focus_moves() {
+        let lens = doublet();
+        // Mark the cached bounds, which a lens with the same elements picks up without tracing
+        let mark = || {
+            if let Some((_, bounds)) = lens.pupil_cache.lock().unwrap().as_mut() {
+                bounds[0] = bounds2::square(7.);
+            }
+        };
+        mark();
+        let mut clone = lens.clone();
+        clone.prepare(1000., 1.5);
+        assert_eq!(clone.exit_pupil_bounds[0].max_x, 7.);
+
+        clone.prepare(500., 1.5);
+        assert!(clone.exit_pupil_bounds[0].max_x < 7.);
+        mark();
+        let mut stopped = clone.clone().with_stop_diameter(4.);
+        stopped.prepare(500., 1.5);
+        assert!(stopped.exit_pupil_bounds[0].max_x < 7.);
+    }
+}
@@ -0,0 +1,193 @@
+use crate::libvec::{color, dot, orthonormal_basis, point3, unit_vector, vec3};
+
+pub enum light {
+    Point(point_light),
+    Spot(spot_light),
+    Directional(directional_light),
+    Sun(sun_light),
+}
+
+pub struct light_sample {
+    pub wi: vec3,      // unit direction from the shading point toward the light
+    pub li: color,     // incident radiance arriving along wi
+    pub distance: f64, // distance to the light (infinite for directional lights)
+}
+
+impl light {
+    pub fn sample_li(&self, p: point3) -> Option<light_sample> {
+        match self {
+            light::Point(l) => l.sample_li(p),
+            light::Spot(l) => l.sample_li(p),
+            light::Directional(l) => l.sample_li(p),
+            light::Sun(l) => l.sample_li(p),
+        }
+    }
+}
+
+pub struct point_light {
+    position: point3,
+    intensity: color,
+}
+
+impl point_light {
+    pub fn from(position: point3, intensity: color) -> point_light {
+        point_light {
+            position,
+            intensity,
+        }
+    }
+
+    pub fn sample_li(&self, p: point3) -> Option<light_sample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0. {
+            return None;
+        }
+
+        // Inverse-square falloff
+        Some(light_sample {
+            wi: unit_vector(to_light),
+            li: self.intensity / distance_squared,
+            distance: distance_squared.sqrt(),
+        })
+    }
+}
+
+pub struct spot_light {
+    position: point3,
+    direction: vec3,
+    intensity: color,
+    cos_total_width: f64,
+    cos_falloff_start: f64,
+}
+
+impl spot_light {
+    pub fn from(
+        position: point3,
+        target: point3,
+        intensity: color,
+        total_width: f64,   // cone half-angle in degrees
+        falloff_start: f64, // half-angle in degrees where the falloff begins
+    ) -> spot_light {
+        spot_light {
+            position,
+            direction: unit_vector(target - position),
+            intensity,
+            cos_total_width: total_width.to_radians().cos(),
+            cos_falloff_start: falloff_start.min(total_width).to_radians().cos(),
+        }
+    }
+
+    pub fn sample_li(&self, p: point3) -> Option<light_sample> {
+        let to_light = self.position - p;
+        let distance_squared = to_light.length_squared();
+        if distance_squared == 0. {
+            return None;
+        }
+
+        let wi = unit_vector(to_light);
+        let falloff = self.falloff(dot(-wi, self.direction));
+        if falloff <= 0. {
+            return None;
+        }
+
+        Some(light_sample {
+            wi,
+            li: falloff * self.intensity / distance_squared,
+            distance: distance_squared.sqrt(),
+        })
+    }
+
+    fn falloff(&self, cos_theta: f64) -> f64 {
+        // Smooth transition from full intensity to zero across the edge of the cone
+        if cos_theta >= self.cos_falloff_start {
+            return 1.;
+        }
+        if cos_theta <= self.cos_total_width {
+            return 0.;
+        }
+        let t =
+            (cos_theta - self.cos_total_width) / (self.cos_falloff_start - self.cos_total_width);
+        t * t * (3. - 2. * t)
+    }
+}
+
+pub struct directional_light {
+    direction: vec3, // direction the light travels in
+    irradiance: color,
+}
+
+impl directional_light {
+    pub fn from(direction: vec3, irradiance: color) -> directional_light {
+        directional_light {
+            direction: unit_vector(direction),
+            irradiance,
+        }
+    }
+
+    pub fn sample_li(&self, _p: point3) -> Option<light_sample> {
+        Some(light_sample {
+            wi: -self.direction,
+            li: self.irradiance,
+            distance: f64::INFINITY,
+        })
+    }
+}
+
+pub struct sun_light {
+    direction: vec3, // unit direction toward the center of the disk
+    radiance: color,
+    cos_radius: f64,
+}
+
+impl sun_light {
+    pub fn from(direction: vec3, radiance: color, angular_radius: f64) -> sun_light {
+        sun_light {
+            direction: unit_vector(direction),
+            radiance,
+            cos_radius: angular_radius.cos(),
+        }
+    }
+
+    pub fn sample_li(&self, _p: point3) -> Option<light_sample> {
+        // Uniformly sample a direction inside the cone subtended by the disk
+        let cos_theta = 1. - rand::random::<f64>() * (1. - self.cos_radius);
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * std::f64::consts::PI * rand::random::<f64>();
+        let (t, b) = orthonormal_basis(self.direction);
+
+        let solid_angle = 2. * std::f64::consts::PI * (1. - self.cos_radius);
+        Some(light_sample {
+            wi: sin_theta * phi.cos() * t + sin_theta * phi.sin() * b + cos_theta * self.direction,
+            li: self.radiance * solid_angle,
+            distance: f64::INFINITY,
+        })
+    }
+}
+
+#[macro_export]
+macro_rules! point_light {
+    ($position:expr, $intensity:expr) => {
+        light::Point(point_light::from($position, $intensity))
+    };
+}
+
+#[macro_export]
+macro_rules! spot_light {
+    ($position:expr, $target:expr, $intensity:expr, $total_width:expr, $falloff_start:expr) => {
+        light::Spot(spot_light::from(
+            $position,
+            $target,
+            $intensity,
+            $total_width,
+            $falloff_start,
+        ))
+    };
+}
+
+#[macro_export]
+macro_rules! directional_light {
+    ($direction:expr, $irradiance:expr) => {
+        light::Directional(directional_light::from($direction, $irradiance))
+    };
+}
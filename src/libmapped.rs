@@ -0,0 +1,262 @@
+use std::sync::Arc;
+
+use crate::{
+    libhittable::{hit_record, scatter, scatter_record},
+    libmaterial::material,
+    libray::ray,
+    libtexture::texture,
+    libvec::{color, cross, dot, orthonormal_basis, unit_vector, vec3},
+};
+
+// Step in texture space for finite differences of bump maps
+const BUMP_DELTA: f64 = 1e-3;
+
+pub enum surface_map {
+    Normal(Arc<texture>),    // tangent space normals encoded as RGB
+    Bump(Arc<texture>, f64), // height texture and its scale
+}
+
+#[derive(Clone, Copy)]
+pub enum alpha_mode {
+    Stochastic,     // pass through with probability 1 - opacity
+    Threshold(f64), // pass through wherever opacity falls below the cutoff
+}
+
+// Wraps any material, perturbing the shading normal before it scatters and
+// optionally cutting holes into the surface with an opacity texture
+pub struct mapped {
+    base: Arc<material>,
+    map: Option<surface_map>,
+    opacity: Option<(Arc<texture>, alpha_mode)>,
+}
+
+impl mapped {
+    pub fn from(base: &Arc<material>) -> mapped {
+        mapped {
+            base: base.clone(),
+            map: None,
+            opacity: None,
+        }
+    }
+
+    pub fn normal(base: &Arc<material>, normal_map: &Arc<texture>) -> mapped {
+        mapped {
+            map: Some(surface_map::Normal(normal_map.clone())),
+            ..mapped::from(base)
+        }
+    }
+
+    pub fn bump(base: &Arc<material>, height: &Arc<texture>, scale: f64) -> mapped {
+        mapped {
+            map: Some(surface_map::Bump(height.clone(), scale)),
+            ..mapped::from(base)
+        }
+    }
+
+    // Opacity is the average of the texture channels, 1 being fully opaque
+    pub fn with_opacity(mut self, opacity: &Arc<texture>, mode: alpha_mode) -> mapped {
+        self.opacity = Some((opacity.clone(), mode));
+        self
+    }
+
+    // Fraction of light blocked at the hit point
+    pub fn opacity(&self, rec: &hit_record) -> f64 {
+        let (texture, mode) = match &self.opacity {
+            Some(o) => o,
+            None => return self.base.opacity(rec),
+        };
+        let c = texture.value(rec.u, rec.v, rec.p);
+        let alpha = ((c.x + c.y + c.z) / 3.).clamp(0., 1.);
+        let alpha = match mode {
+            alpha_mode::Stochastic => alpha,
+            alpha_mode::Threshold(cutoff) => {
+                if alpha < *cutoff {
+                    0.
+                } else {
+                    1.
+                }
+            }
+        };
+        alpha * self.base.opacity(rec)
+    }
+
+    // Whether a ray should pass through the surface at this hit
+    pub fn is_cut_out(&self, rec: &hit_record) -> bool {
+        let alpha = self.opacity(rec);
+        alpha < 1. && rand::random::<f64>() >= alpha
+    }
+
+    // A mapped base is applied first, this map then perturbs its normal further
+    pub fn perturb_normal(&self, rec: &mut hit_record) {
+        self.base.perturb_normal(rec);
+        let map = match &self.map {
+            Some(map) => map,
+            None => return,
+        };
+
+        // Work with the outward normal, then face it toward the ray again
+        let n = if rec.front_face {
+            rec.normal
+        } else {
+            -rec.normal
+        };
+        let (dpdu, dpdv) = tangents(rec, n);
+
+        let perturbed = match map {
+            surface_map::Normal(map) => {
+                let t = 2. * map.value(rec.u, rec.v, rec.p) - color::from(1., 1., 1.);
+                let tangent = unit_vector(dpdu - dot(n, dpdu) * n);
+                let bitangent = cross(n, tangent);
+                t.x * tangent + t.y * bitangent + t.z * n
+            }
+            surface_map::Bump(height, scale) => {
+                let h = |u: f64, v: f64, p: vec3| {
+                    let c = height.value(u, v, p);
+                    scale * (c.x + c.y + c.z) / 3.
+                };
+                let displace = h(rec.u, rec.v, rec.p);
+                let u_displace = h(rec.u + BUMP_DELTA, rec.v, rec.p + BUMP_DELTA * dpdu);
+                let v_displace = h(rec.u, rec.v + BUMP_DELTA, rec.p + BUMP_DELTA * dpdv);
+
+                let bumped_dpdu = dpdu + (u_displace - displace) / BUMP_DELTA * n;
+                let bumped_dpdv = dpdv + (v_displace - displace) / BUMP_DELTA * n;
+                let bumped = cross(bumped_dpdu, bumped_dpdv);
+                if dot(bumped, n) < 0. {
+                    -bumped
+                } else {
+                    bumped
+                }
+            }
+        };
+
+        if perturbed.near_zero() {
+            return;
+        }
+        let perturbed = unit_vector(perturbed);
+        rec.normal = if rec.front_face {
+            perturbed
+        } else {
+            -perturbed
+        };
+    }
+
+    pub fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
+        self.base.scatter(r_in, rec, srec)
+    }
+
+    pub fn eval(&self, r_in: &ray, rec: &hit_record, wi: vec3) -> color {
+        self.base.eval(r_in, rec, wi)
+    }
+}
+
+fn tangents(rec: &hit_record, n: vec3) -> (vec3, vec3) {
+    // Fall back to an arbitrary frame where the parameterization degenerates
+    if rec.dpdu.near_zero() || rec.dpdv.near_zero() {
+        let (t, b) = orthonormal_basis(n);
+        return (t, b);
+    }
+    (rec.dpdu, rec.dpdv)
+}
+
+#[macro_export]
+macro_rules! normal_mapped {
+    ($base:expr, $normal_map:expr) => {
+        std::sync::Arc::new(material::Mapped(mapped::normal($base, $normal_map)))
+    };
+}
+
+#[macro_export]
+macro_rules! bump_mapped {
+    ($base:expr, $height:expr, $scale:expr) => {
+        std::sync::Arc::new(material::Mapped(mapped::bump($base, $height, $scale)))
+    };
+}
+
+#[macro_export]
+macro_rules! cutout {
+    ($base:expr, $opacity:expr) => {
+        std::sync::Arc::new(material::Mapped(
+            mapped::from($base).with_opacity($opacity, alpha_mode::Threshold(0.5)),
+        ))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libmaterial::lambertian;
+    use crate::libtexture::image_texture;
+    use crate::libvec::point3;
+
+    // Hit on a surface facing +z, seen from outside unless `inside`, stretched to twice
+    // the length along u
+    fn hit(inside: bool) -> hit_record<'static> {
+        let r = ray::from(
+            point3::from(0., 0., if inside { -1. } else { 1. }),
+            vec3::from(0., 0., if inside { 1. } else { -1. }),
+        );
+        let mut rec = hit_record::new();
+        rec.set_face_normal(r, vec3::from(0., 0., 1.));
+        rec.u = 0.5005;
+        rec.v = 0.5;
+        rec.dpdu = vec3::from(2., 0., 0.);
+        rec.dpdv = vec3::from(0., 1., 0.);
+        rec
+    }
+
+    fn assert_normal(rec: &hit_record, expected: vec3) {
+        assert!((rec.normal.length() - 1.).abs() < 1e-9);
+        assert!((rec.normal - unit_vector(expected)).length() < 1e-6);
+    }
+
+    #[test]
+    fn normal_map_tilts_the_normal_in_the_tangent_frame() {
+        let diffuse: Arc<material> = crate::lambertian!(0.5, 0.5, 0.5);
+        // Tangent space (0.5, 0, 1), leaning toward the u tangent
+        let map = Arc::new(texture::Solid(color::from(0.75, 0.5, 1.)));
+        let mapped = mapped::normal(&diffuse, &map);
+
+        let mut rec = hit(false);
+        mapped.perturb_normal(&mut rec);
+        assert_normal(&rec, vec3::from(0.5, 0., 1.));
+
+        // From inside, the perturbed normal still faces the ray
+        let mut rec = hit(true);
+        mapped.perturb_normal(&mut rec);
+        assert_normal(&rec, -vec3::from(0.5, 0., 1.));
+    }
+
+    #[test]
+    fn bump_map_tilts_the_normal_against_the_slope() {
+        let diffuse: Arc<material> = crate::lambertian!(0.5, 0.5, 0.5);
+        // Height rising by one per unit of u, scaled by a half over two units of surface
+        let ramp = (0..1000)
+            .map(|i| color::from(1., 1., 1.) * (i as f64 / 1000.))
+            .collect();
+        let height = Arc::new(texture::Image(image_texture::from(1000, 1, ramp)));
+        let mapped = mapped::bump(&diffuse, &height, 0.5);
+
+        let mut rec = hit(false);
+        mapped.perturb_normal(&mut rec);
+        assert_normal(&rec, vec3::from(-0.25, 0., 1.));
+
+        // A flat height leaves the normal alone
+        let flat = Arc::new(texture::Solid(color::from(0.3, 0.3, 0.3)));
+        let mut rec = hit(false);
+        mapped::bump(&diffuse, &flat, 0.5).perturb_normal(&mut rec);
+        assert_normal(&rec, vec3::from(0., 0., 1.));
+    }
+
+    #[test]
+    fn nested_maps_combine_their_opacity() {
+        let diffuse: Arc<material> = crate::lambertian!(0.5, 0.5, 0.5);
+        let half = Arc::new(texture::Solid(color::from(0.5, 0.5, 0.5)));
+        let inner: Arc<material> = Arc::new(material::Mapped(
+            mapped::from(&diffuse).with_opacity(&half, alpha_mode::Stochastic),
+        ));
+        let outer: Arc<material> = Arc::new(material::Mapped(
+            mapped::from(&inner).with_opacity(&half, alpha_mode::Stochastic),
+        ));
+        assert!((outer.opacity(&hit_record::new()) - 0.25).abs() < 1e-12);
+    }
+}
@@ -1,13 +1,43 @@
 use crate::{
-    libhittable::hit_record,
+    libhittable::{hit_record, scatter_record},
+    liblayered::layered,
+    libmapped::mapped,
+    libmicrofacet::{
+        fresnel_conductor, fresnel_dielectric, ggx_d, ggx_g1, ggx_g2, reflect_local, refract_local,
+        sample_ggx_vndf,
+    },
+    libmix::mix,
+    libprincipled::principled,
     libray::ray,
-    libvec::{color, dot, min, random_unit_vector, reflect, refract, unit_vector},
+    libspectrum::spectral,
+    libsubsurface::subsurface,
+    libthinfilm::{complex, thin_film},
+    libvec::{color, dot, frame, orthonormal_basis, random_unit_vector, unit_vector, vec3},
 };
 
 pub enum material {
     Lambertian(lambertian),
     Metal(metal),
     Dielectric(dielectric),
+    Isotropic(isotropic),
+    HenyeyGreenstein(henyey_greenstein),
+    Principled(principled),
+    Layered(layered),
+    Mapped(mapped),
+    Mix(mix),
+    Subsurface(subsurface),
+}
+
+impl material {
+    // Whether a map sits anywhere in the material, including inside mixes and coats
+    pub fn has_map(&self) -> bool {
+        match self {
+            material::Mapped(_) => true,
+            material::Mix(m) => m.has_map(),
+            material::Layered(l) => l.has_map(),
+            _ => false,
+        }
+    }
 }
 
 pub struct lambertian {
@@ -19,95 +49,483 @@ impl lambertian {
         lambertian { albedo }
     }
 
-    pub fn scatter(
-        &self,
-        _r_in: &ray,
-        rec: &hit_record,
-        attenuation: &mut color,
-        scattered: &mut ray,
-    ) -> bool {
+    pub fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
         let mut scatter_direction = rec.normal + random_unit_vector();
 
         if scatter_direction.near_zero() {
             scatter_direction = rec.normal;
         }
 
-        *scattered = ray::from(rec.p, scatter_direction);
-        *attenuation = self.albedo;
+        srec.scattered = r_in.spawn(rec.p, scatter_direction);
+        srec.attenuation = spectral(self.albedo, r_in.lambda);
+        srec.is_specular = false;
         true
     }
+
+    pub fn eval(&self, r_in: &ray, rec: &hit_record, wi: vec3) -> color {
+        let cosine = dot(rec.normal, wi);
+        if cosine <= 0. {
+            return color::new();
+        }
+        spectral(self.albedo, r_in.lambda) * (cosine / std::f64::consts::PI)
+    }
 }
 
+// Below this GGX alpha a surface is treated as a perfect mirror
+pub const SMOOTH_ALPHA: f64 = 1e-3;
+
+// Microfacet conductor with a GGX distribution and complex index of refraction
 pub struct metal {
-    albedo: color,
-    roughness: f64, // fuzz
+    eta: color,
+    k: color,
+    alpha: f64,
+    film: Option<thin_film>,
 }
 
 impl metal {
+    // Artist-friendly conductor: `albedo` is the reflectivity at normal incidence
     pub fn from(albedo: color, roughness: f64) -> metal {
-        metal { albedo, roughness }
+        let (eta, k) = reflectivity_to_ior(albedo, albedo);
+        metal::conductor(eta, k, roughness)
+    }
+
+    pub fn conductor(eta: color, k: color, roughness: f64) -> metal {
+        let roughness = roughness.clamp(0., 1.);
+        metal {
+            eta,
+            k,
+            alpha: roughness * roughness,
+            film: None,
+        }
+    }
+
+    // Coat with a thin film of the given thickness in nm
+    pub fn with_film(mut self, thickness: f64, ior: f64) -> metal {
+        self.film = Some(thin_film::from(thickness, ior));
+        self
+    }
+
+    fn fresnel(&self, cos_theta: f64, lambda: vec3) -> color {
+        // Optical constants at the sampled wavelengths
+        let eta = spectral(self.eta, lambda);
+        let k = spectral(self.k, lambda);
+        match &self.film {
+            Some(film) => film.reflectance(
+                cos_theta,
+                1.,
+                [
+                    complex::from(eta.x, k.x),
+                    complex::from(eta.y, k.y),
+                    complex::from(eta.z, k.z),
+                ],
+                lambda,
+            ),
+            None => fresnel_conductor(cos_theta, eta, k),
+        }
+    }
+
+    pub fn gold(roughness: f64) -> metal {
+        metal::conductor(
+            color::from(0.143, 0.374, 1.442),
+            color::from(3.983, 2.385, 1.603),
+            roughness,
+        )
+    }
+
+    pub fn copper(roughness: f64) -> metal {
+        metal::conductor(
+            color::from(0.200, 0.924, 1.102),
+            color::from(3.912, 2.452, 2.142),
+            roughness,
+        )
+    }
+
+    pub fn aluminium(roughness: f64) -> metal {
+        metal::conductor(
+            color::from(1.657, 0.880, 0.521),
+            color::from(9.224, 6.270, 4.837),
+            roughness,
+        )
+    }
+
+    pub fn silver(roughness: f64) -> metal {
+        metal::conductor(
+            color::from(0.155, 0.117, 0.138),
+            color::from(4.828, 3.122, 2.147),
+            roughness,
+        )
+    }
+
+    pub fn is_specular(&self) -> bool {
+        self.alpha < SMOOTH_ALPHA
+    }
+
+    pub fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
+        let shading = frame::from_normal(rec.normal);
+        let wo = shading.to_local(-unit_vector(r_in.direction));
+        if wo.z <= 0. {
+            return false;
+        }
+
+        srec.is_specular = self.is_specular();
+        if self.is_specular() {
+            let wi = vec3::from(-wo.x, -wo.y, wo.z);
+            srec.scattered = r_in.spawn(rec.p, shading.to_world(wi));
+            srec.attenuation = self.fresnel(wo.z, r_in.lambda);
+            return true;
+        }
+
+        // Sample a visible microfacet and mirror about it
+        let m = sample_ggx_vndf(wo, self.alpha);
+        let wi = reflect_local(wo, m);
+        if wi.z <= 0. {
+            return false;
+        }
+
+        srec.scattered = r_in.spawn(rec.p, shading.to_world(wi));
+        srec.attenuation = self.fresnel(dot(wo, m), r_in.lambda)
+            * (ggx_g2(wo, wi, self.alpha) / ggx_g1(wo, self.alpha));
+        true
     }
 
-    pub fn scatter(
-        &self,
-        r_in: &ray,
-        rec: &hit_record,
-        attenuation: &mut color,
-        scattered: &mut ray,
-    ) -> bool {
-        let reflected = reflect(&unit_vector(r_in.direction), &rec.normal);
-        *scattered = ray::from(rec.p, reflected + self.roughness * random_unit_vector());
-        *attenuation = self.albedo;
-        dot(scattered.direction, rec.normal) > 0.
+    pub fn eval(&self, r_in: &ray, rec: &hit_record, wi: vec3) -> color {
+        if self.is_specular() {
+            // Delta lights never line up with the mirror direction
+            return color::new();
+        }
+
+        let shading = frame::from_normal(rec.normal);
+        let wo = shading.to_local(-unit_vector(r_in.direction));
+        let wi = shading.to_local(wi);
+        if wo.z <= 0. || wi.z <= 0. {
+            return color::new();
+        }
+
+        let h = unit_vector(wo + wi);
+        self.fresnel(dot(wo, h), r_in.lambda)
+            * (ggx_d(h, self.alpha) * ggx_g2(wo, wi, self.alpha) / (4. * wo.z))
+    }
+}
+
+fn reflectivity_to_ior(reflectivity: color, edge_tint: color) -> (color, color) {
+    // Gulbrandsen 2014 mapping from reflectivity and edge tint to complex IOR
+    let channel = |r: f64, g: f64| {
+        let r = r.clamp(0., 0.99);
+        let n_min = (1. - r) / (1. + r);
+        let n_max = (1. + r.sqrt()) / (1. - r.sqrt());
+        let n = g * n_min + (1. - g) * n_max;
+        let k2 = ((n + 1.) * (n + 1.) * r - (n - 1.) * (n - 1.)) / (1. - r);
+        (n, k2.max(0.).sqrt())
+    };
+
+    let (nx, kx) = channel(reflectivity.x, edge_tint.x);
+    let (ny, ky) = channel(reflectivity.y, edge_tint.y);
+    let (nz, kz) = channel(reflectivity.z, edge_tint.z);
+    (color::from(nx, ny, nz), color::from(kx, ky, kz))
+}
+
+// Wavelength dependence of the index of refraction, with wavelengths in micrometres
+pub enum dispersion {
+    None,
+    Cauchy(f64, f64),              // n = a + b / l^2
+    Sellmeier([f64; 3], [f64; 3]), // n^2 = 1 + sum(b_i l^2 / (l^2 - c_i))
+}
+
+impl dispersion {
+    pub fn ior_at(&self, lambda: f64) -> Option<f64> {
+        let l2 = (lambda / 1000.) * (lambda / 1000.);
+        match self {
+            dispersion::None => None,
+            dispersion::Cauchy(a, b) => Some(a + b / l2),
+            dispersion::Sellmeier(b, c) => {
+                let n2 = 1. + (0..3).map(|i| b[i] * l2 / (l2 - c[i])).sum::<f64>();
+                Some(n2.max(1.).sqrt())
+            }
+        }
     }
 }
 
 pub struct dielectric {
-    ior: f64, // Index of Refraction
+    ior: f64,          // Index of Refraction
+    absorption: color, // Beer-Lambert absorption coefficient per unit distance
+    alpha: f64,        // GGX roughness, zero for perfectly smooth glass
+    dispersion: dispersion,
+    film: Option<thin_film>,
 }
 
 impl dielectric {
     pub fn from(ior: f64) -> dielectric {
-        dielectric { ior }
-    }
-
-    pub fn scatter(
-        &self,
-        r_in: &ray,
-        rec: &hit_record,
-        attenuation: &mut color,
-        scattered: &mut ray,
-    ) -> bool {
-        *attenuation = color::from(1., 1., 1.);
-        let refraction_ratio = if rec.front_face {
-            1. / self.ior
+        dielectric::with_absorption(ior, color::new())
+    }
+
+    pub fn with_absorption(ior: f64, absorption: color) -> dielectric {
+        dielectric {
+            ior,
+            absorption,
+            alpha: 0.,
+            dispersion: dispersion::None,
+            film: None,
+        }
+    }
+
+    // Dispersive glass. Outside spectral mode the IOR at the sodium D line is used.
+    pub fn cauchy(a: f64, b: f64) -> dielectric {
+        dielectric::dispersive(dispersion::Cauchy(a, b))
+    }
+
+    pub fn sellmeier(b: [f64; 3], c: [f64; 3]) -> dielectric {
+        dielectric::dispersive(dispersion::Sellmeier(b, c))
+    }
+
+    fn dispersive(dispersion: dispersion) -> dielectric {
+        let mut d = dielectric::from(dispersion.ior_at(589.3).unwrap_or(1.5));
+        d.dispersion = dispersion;
+        d
+    }
+
+    // Tinted glass that lets `transmittance` through after travelling `distance` inside
+    pub fn with_transmittance(ior: f64, transmittance: color, distance: f64) -> dielectric {
+        let absorption = |t: f64| -t.clamp(1e-6, 1.).ln() / distance;
+        dielectric::with_absorption(
+            ior,
+            color::from(
+                absorption(transmittance.x),
+                absorption(transmittance.y),
+                absorption(transmittance.z),
+            ),
+        )
+    }
+
+    // Frosted glass scattering through a GGX microfacet interface (Walter et al. 2007)
+    pub fn rough(ior: f64, roughness: f64) -> dielectric {
+        dielectric::from(ior).roughened(roughness)
+    }
+
+    pub fn roughened(mut self, roughness: f64) -> dielectric {
+        let roughness = roughness.clamp(0., 1.);
+        self.alpha = roughness * roughness;
+        self
+    }
+
+    // Coat the outside with a thin film of the given thickness in nm
+    pub fn with_film(mut self, thickness: f64, ior: f64) -> dielectric {
+        self.film = Some(thin_film::from(thickness, ior));
+        self
+    }
+
+    pub fn is_specular(&self) -> bool {
+        self.alpha < SMOOTH_ALPHA
+    }
+
+    // Reflectance per channel, which only differs between channels under a thin film
+    fn fresnel(&self, cos_theta: f64, eta: f64, front_face: bool, lambda: vec3) -> color {
+        match &self.film {
+            Some(film) => {
+                let n_incident = if front_face { 1. } else { 1. / eta };
+                let n_substrate = complex::real(n_incident * eta);
+                film.reflectance(cos_theta, n_incident, [n_substrate; 3], lambda)
+            }
+            None => {
+                let f = fresnel_dielectric(cos_theta, eta);
+                color::from(f, f, f)
+            }
+        }
+    }
+
+    // Ratio of the IOR on the far side of the interface to the near side
+    fn relative_ior(&self, r_in: &ray, rec: &hit_record) -> f64 {
+        let ior = if r_in.lambda.x > 0. {
+            self.dispersion.ior_at(r_in.lambda.x).unwrap_or(self.ior)
         } else {
             self.ior
         };
 
-        let unit_direction = unit_vector(r_in.direction);
-        let cos_theta = min(dot(-unit_direction, rec.normal), 1.);
-        let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+        if rec.front_face {
+            ior
+        } else {
+            1. / ior
+        }
+    }
 
-        let cannot_refract = refraction_ratio * sin_theta > 1.;
-        let direction;
+    fn transmittance(&self, distance: f64, lambda: vec3) -> color {
+        let absorption = spectral(self.absorption, lambda);
+        color::from(
+            (-absorption.x * distance).exp(),
+            (-absorption.y * distance).exp(),
+            (-absorption.z * distance).exp(),
+        )
+    }
 
-        if cannot_refract || reflectance(cos_theta, refraction_ratio) > rand::random::<f64>() {
-            direction = reflect(&unit_direction, &rec.normal);
+    pub fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
+        // Hitting the inside of the surface means the ray travelled through the medium
+        srec.attenuation = if rec.front_face {
+            color::from(1., 1., 1.)
         } else {
-            direction = refract(&unit_direction, &rec.normal, refraction_ratio);
+            self.transmittance(rec.t * r_in.direction.length(), r_in.lambda)
+        };
+
+        let eta = self.relative_ior(r_in, rec);
+
+        let shading = frame::from_normal(rec.normal);
+        let wo = shading.to_local(-unit_vector(r_in.direction));
+        if wo.z <= 0. {
+            return false;
         }
 
-        *scattered = ray::from(rec.p, direction);
+        let m = if self.is_specular() {
+            vec3::from(0., 0., 1.)
+        } else {
+            sample_ggx_vndf(wo, self.alpha)
+        };
+
+        // Choose between reflection and refraction by the exact Fresnel term. Under total
+        // internal reflection everything is reflected, whatever a film would do.
+        let refracted = refract_local(wo, m, eta);
+        let f = match refracted {
+            Some(_) => self.fresnel(dot(wo, m), eta, rec.front_face, r_in.lambda),
+            None => color::from(1., 1., 1.),
+        };
+        let p_reflect = (f.x + f.y + f.z) / 3.;
+        let wi = if rand::random::<f64>() < p_reflect {
+            let wi = reflect_local(wo, m);
+            if wi.z <= 0. {
+                return false;
+            }
+            srec.attenuation = srec.attenuation * f / p_reflect;
+            wi
+        } else {
+            match refracted {
+                Some(wi) if wi.z < 0. => {
+                    srec.attenuation =
+                        srec.attenuation * (color::from(1., 1., 1.) - f) / (1. - p_reflect);
+                    wi
+                }
+                _ => return false,
+            }
+        };
+
+        if !self.is_specular() {
+            srec.attenuation *= ggx_g2(wo, wi, self.alpha) / ggx_g1(wo, self.alpha);
+        }
+        srec.is_specular = self.is_specular();
+        srec.scattered = r_in.spawn(rec.p, shading.to_world(wi));
+
+        // The path now only follows the hero wavelength
+        if r_in.lambda.x > 0. && !matches!(self.dispersion, dispersion::None) {
+            srec.scattered.lambda = vec3::from(r_in.lambda.x, 0., 0.);
+        }
+        true
+    }
+
+    pub fn eval(&self, r_in: &ray, rec: &hit_record, wi: vec3) -> color {
+        if self.is_specular() {
+            return color::new();
+        }
+
+        let eta = self.relative_ior(r_in, rec);
+
+        let shading = frame::from_normal(rec.normal);
+        let wo = shading.to_local(-unit_vector(r_in.direction));
+        let wi = shading.to_local(wi);
+        if wo.z <= 0. || wi.z == 0. {
+            return color::new();
+        }
+
+        let value = if wi.z > 0. {
+            // Reflection lobe
+            let h = unit_vector(wo + wi);
+            self.fresnel(dot(wo, h), eta, rec.front_face, r_in.lambda)
+                * (ggx_d(h, self.alpha) * ggx_g2(wo, wi, self.alpha) / (4. * wo.z))
+        } else {
+            // Transmission lobe, with the half vector of the refraction pair
+            let mut h = unit_vector(wo + eta * wi);
+            if h.z < 0. {
+                h = -h;
+            }
+            let (o_h, i_h) = (dot(wo, h), dot(wi, h));
+            if o_h <= 0. || i_h >= 0. {
+                return color::new();
+            }
+
+            let denom = i_h + o_h / eta;
+            (color::from(1., 1., 1.) - self.fresnel(o_h, eta, rec.front_face, r_in.lambda))
+                * (ggx_d(h, self.alpha) * ggx_g2(wo, wi, self.alpha) * (i_h * o_h).abs()
+                    / (wo.z * denom * denom))
+        };
+
+        let tint = if rec.front_face {
+            color::from(1., 1., 1.)
+        } else {
+            self.transmittance(rec.t * r_in.direction.length(), r_in.lambda)
+        };
+        value * tint
+    }
+}
+
+pub struct isotropic {
+    albedo: color,
+}
+
+impl isotropic {
+    pub fn from(albedo: color) -> isotropic {
+        isotropic { albedo }
+    }
+
+    pub fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
+        // Scatter uniformly over the sphere of directions
+        srec.scattered = r_in.spawn(rec.p, random_unit_vector());
+        srec.attenuation = spectral(self.albedo, r_in.lambda);
+        srec.is_specular = false;
         true
     }
+
+    pub fn eval(&self, r_in: &ray, _rec: &hit_record, _wi: vec3) -> color {
+        spectral(self.albedo, r_in.lambda) / (4. * std::f64::consts::PI)
+    }
 }
 
-fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
-    // Use Schlick's approximation for reflectance
-    let mut r0 = (1. - ref_idx) / (1. + ref_idx);
-    r0 = r0 * r0;
-    r0 + (1. - r0) * ((1. - cosine).powi(5))
+pub struct henyey_greenstein {
+    albedo: color,
+    g: f64, // asymmetry: -1 backward, 0 isotropic, 1 forward
+}
+
+impl henyey_greenstein {
+    pub fn from(albedo: color, g: f64) -> henyey_greenstein {
+        henyey_greenstein {
+            albedo,
+            g: g.clamp(-0.99, 0.99),
+        }
+    }
+
+    pub fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
+        // Importance sample the phase function around the incoming direction
+        let g = self.g;
+        let xi = rand::random::<f64>();
+        let cos_theta = if g.abs() < 1e-3 {
+            1. - 2. * xi
+        } else {
+            let sqr_term = (1. - g * g) / (1. - g + 2. * g * xi);
+            ((1. + g * g - sqr_term * sqr_term) / (2. * g)).clamp(-1., 1.)
+        };
+        let sin_theta = (1. - cos_theta * cos_theta).max(0.).sqrt();
+        let phi = 2. * std::f64::consts::PI * rand::random::<f64>();
+
+        let d = unit_vector(r_in.direction);
+        let (t, b) = orthonormal_basis(d);
+        let direction = sin_theta * phi.cos() * t + sin_theta * phi.sin() * b + cos_theta * d;
+
+        srec.scattered = r_in.spawn(rec.p, direction);
+        srec.attenuation = spectral(self.albedo, r_in.lambda);
+        srec.is_specular = false;
+        true
+    }
+
+    pub fn eval(&self, r_in: &ray, _rec: &hit_record, wi: vec3) -> color {
+        let cos_theta = dot(unit_vector(r_in.direction), wi);
+        let denom = 1. + self.g * self.g - 2. * self.g * cos_theta;
+        spectral(self.albedo, r_in.lambda)
+            * ((1. - self.g * self.g) / (4. * std::f64::consts::PI * denom * denom.sqrt()))
+    }
 }
 
 #[macro_export]
@@ -142,4 +560,155 @@ macro_rules! dielectric {
     ($ior:expr) => {
         std::sync::Arc::new(material::Dielectric(dielectric::from($ior)))
     };
+
+    ($ior:expr, $absorption:expr) => {
+        std::sync::Arc::new(material::Dielectric(dielectric::with_absorption(
+            $ior,
+            $absorption,
+        )))
+    };
+}
+
+#[macro_export]
+macro_rules! cauchy_dielectric {
+    ($a:expr, $b:expr) => {
+        std::sync::Arc::new(material::Dielectric(dielectric::cauchy($a, $b)))
+    };
+}
+
+#[macro_export]
+macro_rules! rough_dielectric {
+    ($ior:expr, $roughness:expr) => {
+        std::sync::Arc::new(material::Dielectric(dielectric::rough($ior, $roughness)))
+    };
+}
+
+#[macro_export]
+macro_rules! isotropic {
+    ($x:expr, $y:expr, $z:expr) => {
+        std::sync::Arc::new(material::Isotropic(isotropic::from(color::from(
+            $x, $y, $z,
+        ))))
+    };
+
+    ($color:expr) => {
+        std::sync::Arc::new(material::Isotropic(isotropic::from($color)))
+    };
+}
+
+#[macro_export]
+macro_rules! henyey_greenstein {
+    ($color:expr, $g:expr) => {
+        std::sync::Arc::new(material::HenyeyGreenstein(henyey_greenstein::from(
+            $color, $g,
+        )))
+    };
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::libhittable::scatter;
+    use crate::libvec::point3;
+    use std::f64::consts::PI;
+    use std::sync::Arc;
+
+    // Light arriving at the origin of a floor facing +y, at `cos_theta` from its normal
+    pub(crate) fn floor_hit(cos_theta: f64) -> (ray, hit_record<'static>) {
+        let sin_theta = (1. - cos_theta * cos_theta).sqrt();
+        let r_in = ray::from(
+            point3::from(sin_theta, cos_theta, 0.),
+            vec3::from(-sin_theta, -cos_theta, 0.),
+        );
+        let mut rec = hit_record::new();
+        rec.set_face_normal(r_in, vec3::from(0., 1., 0.));
+        (r_in, rec)
+    }
+
+    // Directional albedo of the non-specular lobes from importance sampling and from
+    // integrating eval over the sphere
+    pub(crate) fn albedos(mat: &Arc<material>, cos_theta: f64) -> (f64, f64) {
+        let (r_in, rec) = floor_hit(cos_theta);
+        let n = 200_000;
+        let mut sampled = 0.;
+        let mut integrated = 0.;
+        for _ in 0..n {
+            let mut srec = scatter_record::new();
+            if mat.scatter(&r_in, &rec, &mut srec) && !srec.is_specular {
+                sampled += srec.attenuation.x;
+            }
+            integrated += 4. * PI * mat.eval(&r_in, &rec, random_unit_vector()).x;
+        }
+        (sampled / n as f64, integrated / n as f64)
+    }
+
+    #[test]
+    fn rough_lobes_conserve_energy() {
+        let mirror = Arc::new(material::Metal(metal::conductor(
+            color::from(0.2, 0.2, 0.2),
+            color::from(5., 5., 5.),
+            0.7,
+        )));
+        let frosted = crate::rough_dielectric!(1.5, 0.7);
+        for cos_theta in [0.3, 0.7, 1.] {
+            for mat in [&mirror, &frosted] {
+                let (sampled, integrated) = albedos(mat, cos_theta);
+                assert!(sampled <= 1.005, "albedo {} at {}", sampled, cos_theta);
+                // Light bouncing between microfacets is lost, a third at this roughness
+                assert!(sampled > 0.6, "albedo {} at {}", sampled, cos_theta);
+                assert!(
+                    (sampled - integrated).abs() < 0.05,
+                    "sampled {} but eval integrates to {} at {}",
+                    sampled,
+                    integrated,
+                    cos_theta
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn glass_absorbs_along_the_path_inside() {
+        let absorption = color::from(0.1, 0.5, 2.);
+        let glass = dielectric::with_absorption(1.5, absorption);
+        let tinted = dielectric::with_transmittance(1.5, color::from(0.8, 0.5, 0.2), 2.);
+
+        // Leaving a slab 2 units thick at normal incidence, the direction being 2 units long
+        let r_in = ray::from(point3::new(), vec3::from(0., -2., 0.));
+        let mut rec = hit_record::new();
+        rec.set_face_normal(r_in, vec3::from(0., -1., 0.));
+        rec.t = 1.;
+        assert!(!rec.front_face);
+        for _ in 0..100 {
+            let mut srec = scatter_record::new();
+            assert!(glass.scatter(&r_in, &rec, &mut srec));
+            for i in 0..3 {
+                assert!((srec.attenuation[i] - (-2. * absorption[i]).exp()).abs() < 1e-9);
+            }
+            assert!(tinted.scatter(&r_in, &rec, &mut srec));
+            assert!((srec.attenuation - color::from(0.8, 0.5, 0.2)).length() < 1e-9);
+        }
+
+        // Entering the slab has not travelled through it yet
+        let (r_in, rec) = floor_hit(1.);
+        let mut srec = scatter_record::new();
+        assert!(glass.scatter(&r_in, &rec, &mut srec));
+        assert!((srec.attenuation - color::from(1., 1., 1.)).length() < 1e-9);
+    }
+
+    #[test]
+    fn smooth_glass_splits_light_by_fresnel() {
+        let glass: Arc<material> = crate::dielectric!(1.5);
+        let (r_in, rec) = floor_hit(1.);
+        let (mut reflected, n) = (0, 100_000);
+        for _ in 0..n {
+            let mut srec = scatter_record::new();
+            assert!(glass.scatter(&r_in, &rec, &mut srec));
+            assert!((srec.attenuation - color::from(1., 1., 1.)).length() < 1e-9);
+            if srec.scattered.direction.y > 0. {
+                reflected += 1;
+            }
+        }
+        assert!((reflected as f64 / n as f64 - 0.04).abs() < 0.005);
+    }
 }
@@ -0,0 +1,183 @@
+use std::f64::consts::PI;
+
+use crate::libvec::{color, cross, dot, unit_vector, vec3};
+
+// Trowbridge-Reitz (GGX) distribution of visible normals. All directions are in the
+// local shading frame where the macro surface normal is +z.
+
+pub fn ggx_d(m: vec3, alpha: f64) -> f64 {
+    if m.z <= 0. {
+        return 0.;
+    }
+    let a2 = alpha * alpha;
+    let t = (m.x * m.x + m.y * m.y) / a2 + m.z * m.z;
+    1. / (PI * a2 * t * t)
+}
+
+pub fn ggx_lambda(w: vec3, alpha: f64) -> f64 {
+    let cos2 = w.z * w.z;
+    if cos2 == 0. {
+        return f64::INFINITY;
+    }
+    let tan2 = (w.x * w.x + w.y * w.y) / cos2;
+    ((1. + alpha * alpha * tan2).sqrt() - 1.) / 2.
+}
+
+// Smith masking for a single direction
+pub fn ggx_g1(w: vec3, alpha: f64) -> f64 {
+    1. / (1. + ggx_lambda(w, alpha))
+}
+
+// Height-correlated Smith masking-shadowing
+pub fn ggx_g2(wo: vec3, wi: vec3, alpha: f64) -> f64 {
+    1. / (1. + ggx_lambda(wo, alpha) + ggx_lambda(wi, alpha))
+}
+
+// Sample a microfacet normal visible from wo (Heitz 2018)
+pub fn sample_ggx_vndf(wo: vec3, alpha: f64) -> vec3 {
+    let u1 = rand::random::<f64>();
+    let u2 = rand::random::<f64>();
+
+    // Stretch the view direction to the hemisphere configuration
+    let vh = unit_vector(vec3::from(alpha * wo.x, alpha * wo.y, wo.z));
+
+    let lensq = vh.x * vh.x + vh.y * vh.y;
+    let t1 = if lensq > 0. {
+        vec3::from(-vh.y, vh.x, 0.) / lensq.sqrt()
+    } else {
+        vec3::from(1., 0., 0.)
+    };
+    let t2 = cross(vh, t1);
+
+    // Sample the projected area of the visible hemisphere
+    let r = u1.sqrt();
+    let phi = 2. * PI * u2;
+    let p1 = r * phi.cos();
+    let s = 0.5 * (1. + vh.z);
+    let p2 = (1. - s) * (1. - p1 * p1).sqrt() + s * r * phi.sin();
+    let nh = p1 * t1 + p2 * t2 + (1. - p1 * p1 - p2 * p2).max(0.).sqrt() * vh;
+
+    // Unstretch back to the ellipsoid configuration
+    unit_vector(vec3::from(alpha * nh.x, alpha * nh.y, nh.z.max(0.)))
+}
+
+// Exact unpolarized Fresnel reflectance of a conductor with complex IOR eta + ik
+pub fn fresnel_conductor(cos_theta_i: f64, eta: color, k: color) -> color {
+    let channel = |eta: f64, k: f64| {
+        let cos2 = cos_theta_i.clamp(0., 1.) * cos_theta_i.clamp(0., 1.);
+        let sin2 = 1. - cos2;
+        let eta2 = eta * eta;
+        let k2 = k * k;
+
+        let t0 = eta2 - k2 - sin2;
+        let a2_plus_b2 = (t0 * t0 + 4. * eta2 * k2).sqrt();
+        let t1 = a2_plus_b2 + cos2;
+        let a = (0.5 * (a2_plus_b2 + t0)).max(0.).sqrt();
+        let t2 = 2. * cos_theta_i.clamp(0., 1.) * a;
+        let rs = (t1 - t2) / (t1 + t2);
+
+        let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+        let t4 = t2 * sin2;
+        let rp = rs * (t3 - t4) / (t3 + t4);
+
+        0.5 * (rp + rs)
+    };
+
+    color::from(
+        channel(eta.x, k.x),
+        channel(eta.y, k.y),
+        channel(eta.z, k.z),
+    )
+}
+
+// Exact unpolarized Fresnel reflectance of a dielectric interface, where eta is the
+// ratio of the IOR on the transmitted side to the incident side
+pub fn fresnel_dielectric(cos_theta_i: f64, eta: f64) -> f64 {
+    let mut cos_i = cos_theta_i.clamp(-1., 1.);
+    let mut eta = eta;
+    if cos_i < 0. {
+        eta = 1. / eta;
+        cos_i = -cos_i;
+    }
+
+    let sin2_t = (1. - cos_i * cos_i) / (eta * eta);
+    if sin2_t >= 1. {
+        return 1.; // total internal reflection
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+
+    let r_parl = (eta * cos_i - cos_t) / (eta * cos_i + cos_t);
+    let r_perp = (cos_i - eta * cos_t) / (cos_i + eta * cos_t);
+    (r_parl * r_parl + r_perp * r_perp) / 2.
+}
+
+// Refract wo through the microfacet normal m, None on total internal reflection
+pub fn refract_local(wo: vec3, m: vec3, eta: f64) -> Option<vec3> {
+    let cos_i = dot(wo, m);
+    let sin2_t = (1. - cos_i * cos_i).max(0.) / (eta * eta);
+    if sin2_t >= 1. {
+        return None;
+    }
+    let cos_t = (1. - sin2_t).sqrt();
+    Some(-wo / eta + (cos_i / eta - cos_t) * m)
+}
+
+// Mirror wo about the microfacet normal m
+pub fn reflect_local(wo: vec3, m: vec3) -> vec3 {
+    2. * dot(wo, m) * m - wo
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dielectric_fresnel_limits() {
+        let normal = ((1.5 - 1.) / (1.5 + 1.)) * ((1.5 - 1.) / (1.5 + 1.));
+        assert!((fresnel_dielectric(1., 1.5) - normal).abs() < 1e-12);
+        assert!((fresnel_dielectric(1e-9, 1.5) - 1.).abs() < 1e-6);
+        assert_eq!(fresnel_dielectric(1., 1.), 0.);
+
+        // From inside glass past the critical angle of about 41.8 degrees
+        assert_eq!(fresnel_dielectric(-0.5, 1.5), 1.);
+        assert!(fresnel_dielectric(-0.9, 1.5) < 1.);
+    }
+
+    #[test]
+    fn conductor_without_absorption_is_a_dielectric() {
+        let eta = color::from(1.2, 1.5, 2.4);
+        for k in 1..=10 {
+            let cos_theta = k as f64 / 10.;
+            let f = fresnel_conductor(cos_theta, eta, color::new());
+            assert!((f.x - fresnel_dielectric(cos_theta, 1.2)).abs() < 1e-9);
+            assert!((f.y - fresnel_dielectric(cos_theta, 1.5)).abs() < 1e-9);
+            assert!((f.z - fresnel_dielectric(cos_theta, 2.4)).abs() < 1e-9);
+        }
+        let grazing = fresnel_conductor(0., color::from(0.2, 0.9, 1.1), color::from(3.9, 2.4, 2.2));
+        assert!((grazing - color::from(1., 1., 1.)).length() < 1e-9);
+    }
+
+    #[test]
+    fn ggx_projected_normals_cover_the_surface_once() {
+        // The projected area of the microfacets equals that of the macro surface
+        for alpha in [0.1, 0.5, 1.] {
+            let (n_theta, n_phi) = (2000, 8);
+            let d_theta = PI / 2. / n_theta as f64;
+            let d_phi = 2. * PI / n_phi as f64;
+            let mut area = 0.;
+            for i in 0..n_theta {
+                let theta = (i as f64 + 0.5) * d_theta;
+                for j in 0..n_phi {
+                    let phi = (j as f64 + 0.5) * d_phi;
+                    let m = vec3::from(
+                        theta.sin() * phi.cos(),
+                        theta.sin() * phi.sin(),
+                        theta.cos(),
+                    );
+                    area += ggx_d(m, alpha) * theta.cos() * theta.sin() * d_theta * d_phi;
+                }
+            }
+            assert!((area - 1.).abs() < 1e-3, "alpha {}: {}", alpha, area);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::sync::Arc;
+
+use crate::{
+    libhittable::{hit_record, scatter, scatter_record},
+    libmaterial::material,
+    libmicrofacet::fresnel_dielectric,
+    libray::ray,
+    libtexture::texture,
+    libvec::{color, dot, unit_vector, vec3},
+};
+
+pub enum mix_weight {
+    Constant(f64),
+    Texture(Arc<texture>), // average of the texture channels
+    Fresnel(f64),          // dielectric reflectance for the given IOR
+}
+
+// Blend of two materials, `weight` being the share of the second one
+pub struct mix {
+    a: Arc<material>,
+    b: Arc<material>,
+    weight: mix_weight,
+}
+
+impl mix {
+    pub fn from(a: &Arc<material>, b: &Arc<material>, weight: mix_weight) -> mix {
+        // Maps only act at the top of a material, so they go around the mix instead
+        assert!(
+            !a.has_map() && !b.has_map(),
+            "mixed materials cannot be mapped, map the mix instead"
+        );
+        mix {
+            a: a.clone(),
+            b: b.clone(),
+            weight,
+        }
+    }
+
+    pub fn has_map(&self) -> bool {
+        self.a.has_map() || self.b.has_map()
+    }
+
+    fn weight(&self, r_in: &ray, rec: &hit_record) -> f64 {
+        let w = match &self.weight {
+            mix_weight::Constant(w) => *w,
+            mix_weight::Texture(t) => {
+                let c = t.value(rec.u, rec.v, rec.p);
+                (c.x + c.y + c.z) / 3.
+            }
+            mix_weight::Fresnel(ior) => {
+                let cos_theta = dot(-unit_vector(r_in.direction), rec.normal);
+                fresnel_dielectric(cos_theta.max(0.), *ior)
+            }
+        };
+        w.clamp(0., 1.)
+    }
+
+    pub fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
+        // Pick one material with probability equal to its weight, which also reports whether
+        // the lobe it sampled was specular
+        if rand::random::<f64>() < self.weight(r_in, rec) {
+            self.b.scatter(r_in, rec, srec)
+        } else {
+            self.a.scatter(r_in, rec, srec)
+        }
+    }
+
+    pub fn eval(&self, r_in: &ray, rec: &hit_record, wi: vec3) -> color {
+        let w = self.weight(r_in, rec);
+        (1. - w) * self.a.eval(r_in, rec, wi) + w * self.b.eval(r_in, rec, wi)
+    }
+}
+
+#[macro_export]
+macro_rules! mix {
+    ($a:expr, $b:expr, $weight:expr) => {
+        std::sync::Arc::new(material::Mix(mix::from(
+            $a,
+            $b,
+            mix_weight::Constant($weight),
+        )))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libmapped::{alpha_mode, mapped};
+    use crate::libmaterial::{lambertian, metal};
+    use crate::libvec::reflect;
+
+    #[test]
+    fn specular_flag_follows_the_sampled_lobe() {
+        let mirror: Arc<material> = crate::metal!(0.9, 0.9, 0.9, 0.);
+        let diffuse: Arc<material> = crate::lambertian!(0.5, 0.5, 0.5);
+        let m = mix!(&mirror, &diffuse, 0.5);
+
+        let r_in = ray::from(vec3::from(-1., 1., 0.), vec3::from(1., -1., 0.));
+        let mut rec = hit_record::new();
+        rec.set_face_normal(r_in, vec3::from(0., 1., 0.));
+        let mirrored = unit_vector(reflect(&r_in.direction, &rec.normal));
+
+        let (mut specular, mut diffuse) = (0, 0);
+        for _ in 0..1000 {
+            let mut srec = scatter_record::new();
+            assert!(m.scatter(&r_in, &rec, &mut srec));
+            let on_mirror = (unit_vector(srec.scattered.direction) - mirrored).length() < 1e-9;
+            assert_eq!(srec.is_specular, on_mirror);
+            if on_mirror {
+                specular += 1;
+            } else {
+                diffuse += 1;
+            }
+        }
+        assert!(specular > 0 && diffuse > 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "map the mix instead")]
+    fn mapped_components_are_rejected() {
+        let diffuse: Arc<material> = crate::lambertian!(0.5, 0.5, 0.5);
+        let holes = Arc::new(texture::Solid(color::from(0.2, 0.2, 0.2)));
+        let cut: Arc<material> = crate::cutout!(&diffuse, &holes);
+        mix!(&diffuse, &cut, 0.5);
+    }
+
+    #[test]
+    #[should_panic(expected = "map the mix instead")]
+    fn nested_maps_are_rejected() {
+        let diffuse: Arc<material> = crate::lambertian!(0.5, 0.5, 0.5);
+        let holes = Arc::new(texture::Solid(color::from(0.2, 0.2, 0.2)));
+        let cut: Arc<material> = crate::cutout!(&diffuse, &holes);
+        // Built by hand, as from would already refuse the inner mix
+        let inner = Arc::new(material::Mix(mix {
+            a: diffuse.clone(),
+            b: cut,
+            weight: mix_weight::Constant(0.5),
+        }));
+        mix!(&diffuse, &inner, 0.5);
+    }
+}
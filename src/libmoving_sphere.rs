@@ -0,0 +1,94 @@
+use std::sync::Arc;
+
+use crate::{
+    libaabb::{aabb, surrounding_box},
+    libhittable::hit_record,
+    libmaterial::material,
+    libray::ray,
+    libsphere::sphere,
+    libvec::point3,
+};
+
+// Sphere moving linearly from center0 at time0 to center1 at time1, and resting at those
+// ends before and after, as transform holds its first and last keys
+pub struct moving_sphere {
+    sphere: sphere,
+    center1: point3,
+    time0: f64,
+    time1: f64,
+}
+
+impl moving_sphere {
+    pub fn from(
+        center0: point3,
+        center1: point3,
+        time0: f64,
+        time1: f64,
+        radius: f64,
+        m: &Arc<material>,
+    ) -> moving_sphere {
+        moving_sphere {
+            sphere: sphere::from(center0, radius, m),
+            center1,
+            time0,
+            time1,
+        }
+    }
+
+    pub fn center(&self, time: f64) -> point3 {
+        if self.time1 == self.time0 {
+            return self.sphere.center;
+        }
+        let s = ((time - self.time0) / (self.time1 - self.time0)).clamp(0., 1.);
+        self.sphere.center + s * (self.center1 - self.sphere.center)
+    }
+
+    pub fn hit(&self, r: ray, t_min: f64, t_max: f64, rec: &mut hit_record) -> bool {
+        self.sphere
+            .hit_at(self.center(r.time), r, t_min, t_max, rec)
+    }
+
+    pub fn bounding_box(&self, time0: f64, time1: f64) -> aabb {
+        let box0 = self.sphere.bounding_box();
+        surrounding_box(
+            box0.translate(self.center(time0) - self.sphere.center),
+            box0.translate(self.center(time1) - self.sphere.center),
+        )
+    }
+}
+
+#[macro_export]
+macro_rules! moving_sphere {
+    ($center0:expr, $center1:expr, $time0:expr, $time1:expr, $radius:expr, $material:expr) => {
+        hittable::MovingSphere(moving_sphere::from(
+            $center0, $center1, $time0, $time1, $radius, $material,
+        ))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libmaterial::lambertian;
+    use crate::libvec::color;
+
+    #[test]
+    fn center_rests_outside_the_motion() {
+        let mat = crate::lambertian!(0.5, 0.5, 0.5);
+        let ball = moving_sphere::from(
+            point3::from(0., 0., -3.),
+            point3::from(0., 0., 3.),
+            0.,
+            4.,
+            0.5,
+            &mat,
+        );
+        assert_eq!(ball.center(2.).z, 0.);
+        assert_eq!(ball.center(-1.).z, -3.);
+        assert_eq!(ball.center(6.).z, 3.);
+
+        // Boxes past the end of the motion stay around the resting sphere
+        let late = ball.bounding_box(5., 8.);
+        assert_eq!((late.minimum.z, late.maximum.z), (2.5, 3.5));
+    }
+}
@@ -0,0 +1,96 @@
+use rand::Rng;
+
+use crate::libvec::{dot, point3, unit_vector, vec3};
+
+const POINT_COUNT: usize = 256;
+
+pub struct perlin {
+    ranvec: Vec<vec3>,
+    perm_x: Vec<usize>,
+    perm_y: Vec<usize>,
+    perm_z: Vec<usize>,
+}
+
+impl perlin {
+    pub fn new() -> perlin {
+        perlin {
+            ranvec: (0..POINT_COUNT)
+                .map(|_| unit_vector(vec3::random_range(-1., 1.)))
+                .collect(),
+            perm_x: generate_perm(),
+            perm_y: generate_perm(),
+            perm_z: generate_perm(),
+        }
+    }
+
+    // Gradient noise in roughly [-1, 1]
+    pub fn noise(&self, p: point3) -> f64 {
+        let u = p.x - p.x.floor();
+        let v = p.y - p.y.floor();
+        let w = p.z - p.z.floor();
+
+        let i = p.x.floor() as i64;
+        let j = p.y.floor() as i64;
+        let k = p.z.floor() as i64;
+
+        let mut c = [[[vec3::new(); 2]; 2]; 2];
+        for (di, ci) in c.iter_mut().enumerate() {
+            for (dj, cj) in ci.iter_mut().enumerate() {
+                for (dk, ck) in cj.iter_mut().enumerate() {
+                    *ck = self.ranvec[self.perm_x[((i + di as i64) & 255) as usize]
+                        ^ self.perm_y[((j + dj as i64) & 255) as usize]
+                        ^ self.perm_z[((k + dk as i64) & 255) as usize]];
+                }
+            }
+        }
+
+        perlin_interp(&c, u, v, w)
+    }
+
+    // Sum of `depth` octaves of noise, always non-negative
+    pub fn turb(&self, p: point3, depth: i32) -> f64 {
+        let mut accum = 0.;
+        let mut temp_p = p;
+        let mut weight = 1.;
+
+        for _ in 0..depth {
+            accum += weight * self.noise(temp_p);
+            weight *= 0.5;
+            temp_p *= 2.;
+        }
+
+        accum.abs()
+    }
+}
+
+fn generate_perm() -> Vec<usize> {
+    let mut p: Vec<usize> = (0..POINT_COUNT).collect();
+    let mut rng = rand::thread_rng();
+    for i in (1..POINT_COUNT).rev() {
+        let target = rng.gen_range(0..=i);
+        p.swap(i, target);
+    }
+    p
+}
+
+fn perlin_interp(c: &[[[vec3; 2]; 2]; 2], u: f64, v: f64, w: f64) -> f64 {
+    // Hermite smoothing to avoid grid artifacts
+    let uu = u * u * (3. - 2. * u);
+    let vv = v * v * (3. - 2. * v);
+    let ww = w * w * (3. - 2. * w);
+    let mut accum = 0.;
+
+    for (i, ci) in c.iter().enumerate() {
+        for (j, cj) in ci.iter().enumerate() {
+            for (k, ck) in cj.iter().enumerate() {
+                let (fi, fj, fk) = (i as f64, j as f64, k as f64);
+                let weight_v = vec3::from(u - fi, v - fj, w - fk);
+                accum += (fi * uu + (1. - fi) * (1. - uu))
+                    * (fj * vv + (1. - fj) * (1. - vv))
+                    * (fk * ww + (1. - fk) * (1. - ww))
+                    * dot(*ck, weight_v);
+            }
+        }
+    }
+    accum
+}
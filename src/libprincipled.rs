@@ -0,0 +1,261 @@
+use std::f64::consts::PI;
+
+use crate::{
+    libhittable::{hit_record, scatter_record},
+    libmaterial::dielectric,
+    libmicrofacet::{ggx_d, ggx_g1, ggx_g2, reflect_local, sample_ggx_vndf},
+    libray::ray,
+    libspectrum::spectral,
+    libvec::{color, dot, frame, random_unit_vector, unit_vector, vec3},
+};
+
+// Fixed roughness of the clearcoat lobe
+const CLEARCOAT_ALPHA: f64 = 0.01;
+
+// Disney-style principled BSDF: a diffuse/sheen base, a GGX specular lobe that turns
+// metallic, a clearcoat lobe and a rough glass lobe blended in by `transmission`
+pub struct principled {
+    base_color: color,
+    metallic: f64,
+    roughness: f64,
+    alpha: f64,
+    specular: f64,
+    sheen: f64,
+    clearcoat: f64,
+    transmission: f64,
+    glass: dielectric,
+}
+
+impl principled {
+    pub fn from(base_color: color, metallic: f64, roughness: f64) -> principled {
+        let roughness = roughness.clamp(0.02, 1.);
+        principled {
+            base_color,
+            metallic: metallic.clamp(0., 1.),
+            roughness,
+            alpha: roughness * roughness,
+            specular: 0.5,
+            sheen: 0.,
+            clearcoat: 0.,
+            transmission: 0.,
+            glass: dielectric::rough(1.5, roughness),
+        }
+    }
+
+    pub fn with_specular(mut self, specular: f64) -> principled {
+        self.specular = specular.max(0.);
+        self
+    }
+
+    pub fn with_sheen(mut self, sheen: f64) -> principled {
+        self.sheen = sheen.max(0.);
+        self
+    }
+
+    pub fn with_clearcoat(mut self, clearcoat: f64) -> principled {
+        self.clearcoat = clearcoat.max(0.);
+        self
+    }
+
+    pub fn with_transmission(mut self, transmission: f64, ior: f64) -> principled {
+        self.transmission = transmission.clamp(0., 1.);
+        self.glass = dielectric::rough(ior, self.roughness);
+        self
+    }
+
+    // Probability of following the glass lobe instead of the opaque lobes
+    fn glass_weight(&self) -> f64 {
+        (1. - self.metallic) * self.transmission
+    }
+
+    // Selection probabilities of the diffuse, specular and clearcoat lobes
+    fn lobe_probabilities(&self) -> [f64; 3] {
+        let diffuse = (1. - self.metallic) * max_component(self.base_color).max(0.05);
+        let specular = 1.;
+        let clearcoat = 0.25 * self.clearcoat;
+        let total = diffuse + specular + clearcoat;
+        [diffuse / total, specular / total, clearcoat / total]
+    }
+
+    // Opaque lobes, with the base color already sampled at the ray's wavelengths
+    fn eval_opaque(&self, base_color: color, wo: vec3, wi: vec3) -> color {
+        if wo.z <= 0. || wi.z <= 0. {
+            return color::new();
+        }
+        let h = unit_vector(wo + wi);
+        let cos_d = dot(wi, h);
+
+        // Disney diffuse with retro-reflection at grazing angles. Sheen brightens the color
+        // toward white at grazing angles.
+        let fd90 = 0.5 + 2. * self.roughness * cos_d * cos_d;
+        let fl = schlick_weight(wi.z);
+        let fv = schlick_weight(wo.z);
+        let retro = (1. + (fd90 - 1.) * fl) * (1. + (fd90 - 1.) * fv);
+        let sheen = (self.sheen * schlick_weight(cos_d)).min(1.);
+        let diffuse_color = base_color + sheen * (color::from(1., 1., 1.) - base_color);
+
+        // Specular reflectance tints toward the base color as the surface turns metallic
+        let dielectric_f0 = 0.08 * self.specular;
+        let f0 = (1. - self.metallic) * color::from(dielectric_f0, dielectric_f0, dielectric_f0)
+            + self.metallic * base_color;
+        let fresnel = f0 + schlick_weight(cos_d) * (color::from(1., 1., 1.) - f0);
+        let specular =
+            fresnel * (ggx_d(h, self.alpha) * ggx_g2(wo, wi, self.alpha)) / (4. * wo.z * wi.z);
+
+        // Light reflected by the specular layer on the way in or out never reaches the diffuse
+        // base and sheen, and the clearcoat does the same to everything below it
+        let transmitted = |f0: f64, cos_theta: f64| {
+            let f0 = f0.min(1.);
+            (1. - f0) * (1. - schlick_weight(cos_theta))
+        };
+        let base = (1. - self.metallic)
+            * transmitted(dielectric_f0, wo.z)
+            * transmitted(dielectric_f0, wi.z)
+            * (retro / PI)
+            * diffuse_color;
+
+        let coat = (0.25 * self.clearcoat).min(1.);
+        let under_coat = (1. - coat + coat * transmitted(0.04, wo.z))
+            * (1. - coat + coat * transmitted(0.04, wi.z));
+        let clearcoat = coat
+            * (0.04 + 0.96 * schlick_weight(cos_d))
+            * ggx_d(h, CLEARCOAT_ALPHA)
+            * ggx_g2(wo, wi, CLEARCOAT_ALPHA)
+            / (4. * wo.z * wi.z);
+
+        (under_coat * (base + specular) + color::from(clearcoat, clearcoat, clearcoat)) * wi.z
+    }
+
+    fn pdf_opaque(&self, wo: vec3, wi: vec3) -> f64 {
+        if wo.z <= 0. || wi.z <= 0. {
+            return 0.;
+        }
+        let [p_diffuse, p_specular, p_clearcoat] = self.lobe_probabilities();
+        let h = unit_vector(wo + wi);
+
+        // Visible normal sampling pdf, converted from half vector to reflected direction
+        let vndf = |alpha: f64| ggx_g1(wo, alpha) * ggx_d(h, alpha) / (4. * wo.z);
+
+        p_diffuse * wi.z / PI + p_specular * vndf(self.alpha) + p_clearcoat * vndf(CLEARCOAT_ALPHA)
+    }
+
+    pub fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
+        // Rays inside a transmissive object can only leave through the glass lobe
+        let base_color = spectral(self.base_color, r_in.lambda);
+        if !rec.front_face || rand::random::<f64>() < self.glass_weight() {
+            if !self.glass.scatter(r_in, rec, srec) {
+                return false;
+            }
+            srec.attenuation = srec.attenuation * base_color;
+            return true;
+        }
+
+        let shading = frame::from_normal(rec.normal);
+        let wo = shading.to_local(-unit_vector(r_in.direction));
+        if wo.z <= 0. {
+            return false;
+        }
+
+        // Pick one lobe to sample, then weight by the pdf of the whole mixture
+        let [p_diffuse, p_specular, _] = self.lobe_probabilities();
+        let u = rand::random::<f64>();
+        let wi = if u < p_diffuse {
+            shading.to_local(unit_vector(rec.normal + random_unit_vector()))
+        } else if u < p_diffuse + p_specular {
+            reflect_local(wo, sample_ggx_vndf(wo, self.alpha))
+        } else {
+            reflect_local(wo, sample_ggx_vndf(wo, CLEARCOAT_ALPHA))
+        };
+
+        let pdf = self.pdf_opaque(wo, wi);
+        if wi.z <= 0. || pdf <= 0. {
+            return false;
+        }
+
+        srec.attenuation = self.eval_opaque(base_color, wo, wi) / pdf;
+        srec.scattered = r_in.spawn(rec.p, shading.to_world(wi));
+        srec.is_specular = false;
+        true
+    }
+
+    pub fn eval(&self, r_in: &ray, rec: &hit_record, wi: vec3) -> color {
+        let base_color = spectral(self.base_color, r_in.lambda);
+        let glass = self.glass.eval(r_in, rec, wi) * base_color;
+        if !rec.front_face {
+            return glass;
+        }
+
+        let shading = frame::from_normal(rec.normal);
+        let wo = shading.to_local(-unit_vector(r_in.direction));
+        let opaque = self.eval_opaque(base_color, wo, shading.to_local(wi));
+        (1. - self.glass_weight()) * opaque + self.glass_weight() * glass
+    }
+}
+
+fn schlick_weight(cos_theta: f64) -> f64 {
+    (1. - cos_theta.clamp(0., 1.)).powi(5)
+}
+
+fn max_component(c: color) -> f64 {
+    c.x.max(c.y).max(c.z)
+}
+
+#[macro_export]
+macro_rules! principled {
+    ($base_color:expr, $metallic:expr, $roughness:expr) => {
+        std::sync::Arc::new(material::Principled(principled::from(
+            $base_color,
+            $metallic,
+            $roughness,
+        )))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libmaterial::{material, tests::albedos};
+    use std::sync::Arc;
+
+    // Every lobe: diffuse with sheen, specular, clearcoat, metal and glass
+    fn materials(base_color: color) -> Vec<Arc<material>> {
+        [
+            principled::from(base_color, 0., 1.),
+            principled::from(base_color, 0., 0.2)
+                .with_sheen(1.)
+                .with_clearcoat(1.),
+            principled::from(base_color, 1., 0.4),
+            principled::from(base_color, 0.3, 0.6).with_specular(1.),
+            principled::from(base_color, 0., 0.5).with_transmission(0.5, 1.5),
+        ]
+        .into_iter()
+        .map(|p| Arc::new(material::Principled(p)))
+        .collect()
+    }
+
+    #[test]
+    fn eval_matches_scatter() {
+        for mat in materials(color::from(0.8, 0.5, 0.3)) {
+            for cos_theta in [0.3, 0.7, 1.] {
+                let (sampled, integrated) = albedos(&mat, cos_theta);
+                assert!(
+                    (sampled - integrated).abs() < 0.05,
+                    "sampled {} but eval integrates to {} at {}",
+                    sampled,
+                    integrated,
+                    cos_theta
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn white_reflects_no_more_than_it_receives() {
+        for mat in materials(color::from(1., 1., 1.)) {
+            for cos_theta in [0.1, 0.3, 0.7, 1.] {
+                let (sampled, _) = albedos(&mat, cos_theta);
+                assert!(sampled <= 1.01, "albedo {} at {}", sampled, cos_theta);
+            }
+        }
+    }
+}
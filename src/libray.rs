@@ -4,6 +4,8 @@ use crate::libvec::{point3, vec3};
 pub struct ray {
     pub origin: point3,
     pub direction: vec3,
+    pub lambda: vec3, // sampled wavelengths in nm, zero when rendering in RGB
+    pub time: f64,    // instant within the shutter interval
 }
 
 impl ray {
@@ -11,6 +13,8 @@ impl ray {
         ray {
             origin: point3::new(),
             direction: vec3::new(),
+            lambda: vec3::new(),
+            time: 0.,
         }
     }
 
@@ -18,6 +22,18 @@ impl ray {
         ray {
             origin: orig,
             direction: dir,
+            lambda: vec3::new(),
+            time: 0.,
+        }
+    }
+
+    // Continue the path from a new origin, keeping the wavelengths and time it carries
+    pub fn spawn(&self, orig: point3, dir: vec3) -> ray {
+        ray {
+            origin: orig,
+            direction: dir,
+            lambda: self.lambda,
+            time: self.time,
         }
     }
 
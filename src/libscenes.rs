@@ -0,0 +1,468 @@
+use std::io;
+use std::sync::Arc;
+
+use crate::{
+    libanimation::{camera_animation, interpolation, keyframes},
+    libconstant_medium::constant_medium,
+    libheterogeneous_medium::{density_field, density_grid, heterogeneous_medium, noise_density},
+    libhittable::hittable,
+    libhittable_list::hittable_list,
+    liblayered::layered,
+    liblight::{directional_light, light, point_light, spot_light},
+    libmapped::{alpha_mode, mapped},
+    libmaterial::{dielectric, henyey_greenstein, isotropic, lambertian, material, metal},
+    libmix::{mix, mix_weight},
+    libmoving_sphere::moving_sphere,
+    libprincipled::principled,
+    libsky::sky,
+    libsphere::sphere,
+    libsubsurface::subsurface,
+    libtexture::{image_texture, noise_texture, texture},
+    libtransform::transform,
+    libvec::{color, point3, vec3},
+};
+
+// Length of one turn of the camera around a scene
+const TURNTABLE_SECONDS: f64 = 10.;
+
+// Scenes selected with --scene, each with the camera animation it is seen through
+pub fn names() -> &'static str {
+    "random, lights, daylight, materials, media or motion"
+}
+
+// The media scene takes an optional density grid file, the materials scene an optional
+// tangent space normal map
+pub fn by_name(
+    name: &str,
+    grid: Option<&str>,
+    normal_map: Option<&str>,
+) -> io::Result<(hittable_list, camera_animation)> {
+    match name {
+        "random" => Ok(random()),
+        "lights" => Ok(lights()),
+        "daylight" => Ok(daylight()),
+        "materials" => materials(normal_map),
+        "media" => media(grid),
+        "motion" => Ok(motion()),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("no such scene, expected {}", names()),
+        )),
+    }
+}
+
+fn ground(world: &mut hittable_list) {
+    let ground_material = crate::lambertian!(0.5, 0.5, 0.5);
+    world.add(crate::sphere!(0., -1000., 0., 1000., &ground_material));
+}
+
+// Turntable around a point above the origin, focused on it and without depth of field
+fn turntable_at(lookfrom: point3, vfov: f64) -> camera_animation {
+    let lookat = point3::from(0., 0.5, 0.);
+    let focus_dist = (lookfrom - lookat).length();
+    camera_animation::turntable(lookfrom, lookat, vfov, 0., focus_dist, TURNTABLE_SECONDS)
+}
+
+pub fn random() -> (hittable_list, camera_animation) {
+    let mut world = hittable_list::new();
+    ground(&mut world);
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let choose_mat = rand::random::<f64>();
+            let center = point3::from(
+                a as f64 + 0.9 * rand::random::<f64>(),
+                0.2,
+                b as f64 + 0.9 * rand::random::<f64>(),
+            );
+
+            if (center - point3::from(4., 0.2, 0.)).length() > 0.9 {
+                if choose_mat < 0.4 {
+                    // diffuse
+                    let albedo = color::random() * color::random();
+                    let sphere_material = crate::lambertian!(albedo);
+                    world.add(crate::sphere!(center, 0.2, &sphere_material));
+                } else if choose_mat < 0.6 {
+                    // metal
+                    let albedo = color::random_range(0.5, 1.);
+                    let roughness = rand::random::<f64>();
+                    let sphere_material = crate::metal!(albedo, roughness);
+                    world.add(crate::sphere!(center, 0.2, &sphere_material));
+                } else {
+                    // glass
+                    let sphere_material = crate::dielectric!(1.5);
+                    world.add(crate::sphere!(center, 0.2, &sphere_material));
+                }
+            }
+        }
+    }
+
+    let material1 = crate::dielectric!(1.5);
+    world.add(crate::sphere!(point3::from(0., 1., 0.), 1., &material1));
+
+    let material2 = crate::lambertian!(0.4, 0.2, 0.1);
+    world.add(crate::sphere!(point3::from(-4., 1., 0.), 1., &material2));
+
+    let material3 = crate::metal!(color::from(0.7, 0.6, 0.5), 0.);
+    world.add(crate::sphere!(point3::from(4., 1., 0.), 1., &material3));
+
+    // Turntable starting from the still camera position
+    let lookfrom = point3::from(13., 2., 3.);
+    let lookat = point3::from(0., 0., 0.);
+    let dist_to_focus = 10.;
+    let aperture = 0.1;
+    let animation = camera_animation::turntable(
+        lookfrom,
+        lookat,
+        20.,
+        aperture,
+        dist_to_focus,
+        TURNTABLE_SECONDS,
+    );
+    (world, animation)
+}
+
+// A few spheres at dusk, lit by a point light, a spot light and a low directional light
+pub fn lights() -> (hittable_list, camera_animation) {
+    let mut world = hittable_list::new();
+    ground(&mut world);
+
+    let white = crate::lambertian!(0.8, 0.8, 0.8);
+    let gold = Arc::new(material::Metal(metal::gold(0.3)));
+    world.add(crate::sphere!(0., 1., 0., 1., &white));
+    world.add(crate::sphere!(0.5, 0.5, -2.2, 0.5, &gold));
+    world.add(crate::sphere!(0.5, 0.5, 2.2, 0.5, &white));
+
+    world.add_light(crate::point_light!(
+        point3::from(2., 3., 2.5),
+        color::from(20., 14., 8.)
+    ));
+    world.add_light(crate::spot_light!(
+        point3::from(3., 5., -3.),
+        point3::from(0., 0., -1.5),
+        color::from(60., 60., 80.),
+        25.,
+        15.
+    ));
+    world.add_light(crate::directional_light!(
+        vec3::from(-1., -0.3, 0.4),
+        color::from(0.3, 0.15, 0.1)
+    ));
+
+    // Twilight, with the sun just below the horizon
+    world.set_sky(sky::from(-4., 200., 2., color::from(0.3, 0.3, 0.3)).with_intensity(0.002));
+
+    (world, turntable_at(point3::from(13., 3., 3.), 25.))
+}
+
+// Spheres of the metals with measured optical constants under an afternoon sky. The sky is
+// in cd/m^2, exposed at f/16 and ISO 50 by the sunny 16 rule for the 1/48 s shutter.
+pub fn daylight() -> (hittable_list, camera_animation) {
+    let mut world = hittable_list::new();
+    ground(&mut world);
+
+    let metals = [
+        metal::gold(0.1),
+        metal::copper(0.2),
+        metal::aluminium(0.3),
+        metal::silver(0.05),
+    ];
+    for (k, m) in metals.into_iter().enumerate() {
+        let z = -2.4 + 1.6 * k as f64;
+        world.add(crate::sphere!(
+            0.,
+            0.7,
+            z,
+            0.7,
+            &Arc::new(material::Metal(m))
+        ));
+    }
+    world.set_sky(sky::from(35., 60., 3., color::from(0.3, 0.3, 0.3)).with_intensity(1000.));
+
+    let camera = turntable_at(point3::from(13., 2., 3.), 25.)
+        .with_iso(50.)
+        .with_f_number(16.);
+    (world, camera)
+}
+
+// Tangent space normal map of a grid of round studs, encoded as RGB
+fn studs(size: usize, count: f64) -> image_texture {
+    let mut data = Vec::with_capacity(size * size);
+    for j in 0..size {
+        for i in 0..size {
+            let x = ((i as f64 + 0.5) / size as f64 * count).fract() * 2. - 1.;
+            let y = ((j as f64 + 0.5) / size as f64 * count).fract() * 2. - 1.;
+            let n = if x * x + y * y < 0.8 {
+                vec3::from(0.6 * x, -0.6 * y, 1.)
+            } else {
+                vec3::from(0., 0., 1.)
+            };
+            data.push(0.5 * (n / n.length() + vec3::from(1., 1., 1.)));
+        }
+    }
+    image_texture::from(size, size, data)
+}
+
+// Stripes across u, white where opaque
+fn stripes(size: usize, count: f64) -> image_texture {
+    let data = (0..size)
+        .map(|i| {
+            if ((i as f64 + 0.5) / size as f64 * count).fract() < 0.5 {
+                color::from(1., 1., 1.)
+            } else {
+                color::from(0.2, 0.2, 0.2)
+            }
+        })
+        .collect();
+    image_texture::from(size, 1, data)
+}
+
+// One sphere per material model and option, in rows seen from above. The normal mapped
+// sphere has studs unless a normal map image is given.
+pub fn materials(normal_map: Option<&str>) -> io::Result<(hittable_list, camera_animation)> {
+    let mut world = hittable_list::new();
+    ground(&mut world);
+
+    let red = crate::lambertian!(0.7, 0.1, 0.1);
+    let blue = crate::lambertian!(0.1, 0.2, 0.6);
+    let mirror = crate::metal!(0.9, 0.9, 0.9, 0.);
+    let noise = Arc::new(texture::Noise(noise_texture::from(4.)));
+    let bumps = Arc::new(texture::Image(match normal_map {
+        Some(path) => image_texture::load(path)?,
+        None => studs(64, 8.),
+    }));
+    let bars = Arc::new(texture::Image(stripes(64, 6.)));
+    let half = Arc::new(texture::Solid(color::from(0.5, 0.5, 0.5)));
+
+    let glass = |d: dielectric| Arc::new(material::Dielectric(d));
+    let bk7 = dielectric::sellmeier(
+        [1.039_612_12, 0.231_792_344, 1.010_469_45],
+        [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+    );
+    let materials: Vec<Arc<material>> = vec![
+        // Conductors and thin films
+        Arc::new(material::Metal(metal::gold(0.15))),
+        Arc::new(material::Metal(metal::copper(0.3))),
+        Arc::new(material::Metal(
+            metal::from(color::from(0.8, 0.8, 0.8), 0.).with_film(350., 1.33),
+        )),
+        glass(dielectric::from(1.5).with_film(400., 1.38)),
+        // Dielectrics
+        crate::rough_dielectric!(1.5, 0.3),
+        glass(dielectric::with_absorption(1.5, color::from(0.1, 0.6, 1.2))),
+        glass(dielectric::with_transmittance(1.5, color::from(0.9, 0.5, 0.2), 0.8).roughened(0.1)),
+        crate::cauchy_dielectric!(1.5046, 0.0042),
+        glass(bk7),
+        // Principled
+        Arc::new(material::Principled(
+            principled::from(color::from(0.8, 0.3, 0.1), 0., 0.5)
+                .with_specular(0.5)
+                .with_sheen(0.5)
+                .with_clearcoat(1.),
+        )),
+        crate::principled!(color::from(0.9, 0.7, 0.3), 1., 0.3),
+        Arc::new(material::Principled(
+            principled::from(color::from(1., 1., 1.), 0., 0.1).with_transmission(1., 1.45),
+        )),
+        // Coated, blended and translucent
+        Arc::new(material::Layered(
+            layered::from(&red, 1.5, 0.).with_absorption(color::from(0.2, 0.4, 1.), 0.5),
+        )),
+        crate::mix!(&red, &mirror, 0.3),
+        Arc::new(material::Mix(mix::from(
+            &blue,
+            &mirror,
+            mix_weight::Texture(noise.clone()),
+        ))),
+        Arc::new(material::Mix(mix::from(
+            &blue,
+            &mirror,
+            mix_weight::Fresnel(1.5),
+        ))),
+        crate::subsurface!(
+            color::from(0.95, 0.85, 0.7),
+            color::from(0.3, 0.15, 0.08),
+            1.4
+        ),
+        // Surface detail and cut-outs
+        crate::normal_mapped!(&red, &bumps),
+        crate::bump_mapped!(&blue, &noise, 0.02),
+        crate::cutout!(&red, &bars),
+        Arc::new(material::Mapped(
+            mapped::from(&blue).with_opacity(&half, alpha_mode::Stochastic),
+        )),
+    ];
+
+    for (k, m) in materials.into_iter().enumerate() {
+        let row = (k / 6) as f64;
+        let column = (k % 6) as f64;
+        let center = point3::from(1.8 - 1.2 * row, 0.4, -2.75 + 1.1 * column);
+        world.add(crate::sphere!(center, 0.4, &m));
+    }
+    world.set_sky(sky::from(40., 30., 3., color::from(0.3, 0.3, 0.3)));
+
+    let lookfrom = point3::from(10., 7., 0.);
+    let lookat = point3::from(0., 0., 0.);
+    let focus_dist = (lookfrom - lookat).length();
+    let animation =
+        camera_animation::turntable(lookfrom, lookat, 32., 0., focus_dist, TURNTABLE_SECONDS);
+    Ok((world, animation))
+}
+
+// Torus of density around the y axis, filling the box between the corners
+fn torus_grid(n: usize, min_corner: point3, max_corner: point3) -> density_grid {
+    let at = |i: usize| 2. * i as f64 / (n - 1) as f64 - 1.;
+    let mut data = Vec::with_capacity(n * n * n);
+    for k in 0..n {
+        for j in 0..n {
+            for i in 0..n {
+                let (x, y, z) = (at(i), at(j), at(k));
+                let ring = ((x * x + z * z).sqrt() - 0.6).hypot(y);
+                data.push((1. - ring / 0.3).max(0.) as f32);
+            }
+        }
+    }
+    density_grid::from(n, n, n, data, min_corner, max_corner)
+}
+
+// Smoke, a noisy cloud and a density grid, which is a torus unless a grid file is given
+pub fn media(grid: Option<&str>) -> io::Result<(hittable_list, camera_animation)> {
+    let mut world = hittable_list::new();
+    ground(&mut world);
+
+    let smoke = crate::isotropic!(0.8, 0.8, 0.8);
+    world.add(crate::constant_medium!(
+        crate::sphere!(0., 1., -2.4, 1., &smoke),
+        1.5,
+        &smoke
+    ));
+
+    let cloud = crate::henyey_greenstein!(color::from(0.95, 0.95, 0.95), 0.6);
+    world.add(crate::heterogeneous_medium!(
+        crate::sphere!(0., 1.2, 0., 1.2, &cloud),
+        density_field::Noise(noise_density::from(1.5, 5)),
+        8.,
+        &cloud
+    ));
+
+    let (min_corner, max_corner) = (point3::from(-1., 0., 1.4), point3::from(1., 2., 3.4));
+    let field = match grid {
+        Some(path) => density_grid::load(path, min_corner, max_corner)?,
+        None => torus_grid(32, min_corner, max_corner),
+    };
+    let ember = crate::henyey_greenstein!(color::from(0.9, 0.6, 0.3), 0.3);
+    world.add(crate::heterogeneous_medium!(
+        crate::sphere!(0., 1., 2.4, 1.74, &ember),
+        density_field::Grid(field),
+        10.,
+        &ember
+    ));
+    world.set_sky(sky::from(25., 120., 2.5, color::from(0.3, 0.3, 0.3)));
+
+    Ok((world, turntable_at(point3::from(13., 2., 3.), 25.)))
+}
+
+// Objects moving during the first four seconds, filmed by a camera that pulls back while
+// zooming in and rolling slightly
+pub fn motion() -> (hittable_list, camera_animation) {
+    let mut world = hittable_list::new();
+    ground(&mut world);
+
+    let red = crate::lambertian!(0.7, 0.1, 0.1);
+    let blue = crate::lambertian!(0.1, 0.2, 0.6);
+    let steel = crate::metal!(0.8, 0.8, 0.8, 0.2);
+
+    world.add(crate::moving_sphere!(
+        point3::from(1.5, 0.5, -3.),
+        point3::from(1.5, 0.5, 3.),
+        0.,
+        4.,
+        0.5,
+        &red
+    ));
+    world.add(hittable::Transform(transform::moving(
+        crate::sphere!(0., 0.5, 0., 0.5, &blue),
+        vec3::from(-1., 0., 3.),
+        vec3::from(-1., 0., -3.),
+        0.,
+        4.,
+    )));
+
+    // Bouncing twice a second
+    let bounce = keyframes::from(interpolation::CatmullRom)
+        .with_key(0., vec3::from(0., 0., 0.))
+        .with_key(0.25, vec3::from(0., 1.5, 0.))
+        .with_key(0.5, vec3::from(0., 0., 0.))
+        .looping();
+    world.add(hittable::Transform(transform::animated(
+        crate::sphere!(0., 0.5, 0., 0.5, &steel),
+        bounce,
+    )));
+
+    world.add(crate::transform!(
+        crate::sphere!(0., 1., 0., 1., &steel),
+        vec3::from(-4., 0., 0.)
+    ));
+
+    // Striped shell turning twice while it swells and shrinks every second
+    let bars = Arc::new(texture::Image(stripes(64, 6.)));
+    let spin = keyframes::from(interpolation::Linear)
+        .with_key(0., vec3::from(0., 0., 0.))
+        .with_key(4., vec3::from(0., 720., 0.));
+    let pulse = keyframes::from(interpolation::CatmullRom)
+        .with_key(0., 1.)
+        .with_key(0.5, 1.2)
+        .with_key(1., 1.)
+        .looping();
+    world.add(hittable::Transform(
+        transform::from(
+            crate::sphere!(0., 0., 0., 1., &crate::cutout!(&red, &bars)),
+            vec3::from(-3., 1.2, -4.),
+        )
+        .with_rotation(spin)
+        .with_scale(pulse),
+    ));
+    world.set_sky(sky::from(50., 80., 3., color::from(0.3, 0.3, 0.3)));
+
+    let lookat = point3::from(0., 0.5, 0.);
+    let animation = camera_animation::from(
+        point3::from(8., 2., 2.),
+        lookat,
+        vec3::from(0., 1., 0.),
+        40.,
+        0.05,
+        8.,
+    )
+    .with_lookfrom(
+        keyframes::from(interpolation::Linear)
+            .with_key(0., point3::from(8., 2., 2.))
+            .with_key(4., point3::from(16., 3., 4.)),
+    )
+    .with_lookat(
+        keyframes::from(interpolation::Linear)
+            .with_key(0., lookat)
+            .with_key(4., point3::from(-1., 0.5, 0.)),
+    )
+    .with_vup(
+        keyframes::from(interpolation::CatmullRom)
+            .with_key(0., vec3::from(0., 1., 0.))
+            .with_key(2., vec3::from(0.1, 1., 0.))
+            .with_key(4., vec3::from(0., 1., 0.)),
+    )
+    .with_vfov(
+        keyframes::from(interpolation::CatmullRom)
+            .with_key(0., 40.)
+            .with_key(4., 20.),
+    )
+    .with_aperture(
+        keyframes::from(interpolation::Linear)
+            .with_key(0., 0.05)
+            .with_key(4., 0.2),
+    )
+    .with_focus_dist(
+        keyframes::from(interpolation::Linear)
+            .with_key(0., 8.)
+            .with_key(4., 17.),
+    );
+    (world, animation)
+}
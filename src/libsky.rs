@@ -0,0 +1,193 @@
+use std::f64::consts::PI;
+
+use crate::{
+    libcolor::xyz_to_rgb,
+    liblight::{light, sun_light},
+    libvec::{color, dot, unit_vector, vec3},
+};
+
+// Angular radius of the sun disk as seen from the earth
+const SUN_ANGULAR_RADIUS: f64 = 0.2667 * PI / 180.;
+
+// Extraterrestrial luminance of the sun in kcd/m^2
+const SUN_LUMINANCE: f64 = 2.0e6;
+
+// Preetham et al. 1999 analytic daylight model. Radiance is in kcd/m^2 scaled by `intensity`.
+pub struct sky {
+    sun_direction: vec3,
+    theta_s: f64,
+    perez_Y: [f64; 5],
+    perez_x: [f64; 5],
+    perez_y: [f64; 5],
+    zenith_Y: f64,
+    zenith_x: f64,
+    zenith_y: f64,
+    sun_radiance: color,
+    ground: color,
+    intensity: f64,
+}
+
+impl sky {
+    pub fn from(elevation: f64, azimuth: f64, turbidity: f64, ground_albedo: color) -> sky {
+        // Elevation and azimuth are in degrees; azimuth is measured from +x toward +z
+        let el = elevation.to_radians();
+        let az = azimuth.to_radians();
+        let sun_direction = vec3::from(el.cos() * az.cos(), el.sin(), el.cos() * az.sin());
+
+        // The model is only defined for a sun above the horizon
+        let theta_s = (PI / 2. - el).clamp(0., PI / 2.);
+        let t = turbidity;
+
+        let perez_Y = [
+            0.1787 * t - 1.4630,
+            -0.3554 * t + 0.4275,
+            -0.0227 * t + 5.3251,
+            0.1206 * t - 2.5771,
+            -0.0670 * t + 0.3703,
+        ];
+        let perez_x = [
+            -0.0193 * t - 0.2592,
+            -0.0665 * t + 0.0008,
+            -0.0004 * t + 0.2125,
+            -0.0641 * t - 0.8989,
+            -0.0033 * t + 0.0452,
+        ];
+        let perez_y = [
+            -0.0167 * t - 0.2608,
+            -0.0950 * t + 0.0092,
+            -0.0079 * t + 0.2102,
+            -0.0441 * t - 1.6537,
+            -0.0109 * t + 0.0529,
+        ];
+
+        let chi = (4. / 9. - t / 120.) * (PI - 2. * theta_s);
+        let zenith_Y = ((4.0453 * t - 4.9710) * chi.tan() - 0.2155 * t + 2.4192).max(0.);
+
+        let th = theta_s;
+        let th2 = th * th;
+        let th3 = th2 * th;
+        let zenith_x = t * t * (0.00166 * th3 - 0.00375 * th2 + 0.00209 * th)
+            + t * (-0.02903 * th3 + 0.06377 * th2 - 0.03202 * th + 0.00394)
+            + (0.11693 * th3 - 0.21196 * th2 + 0.06052 * th + 0.25886);
+        let zenith_y = t * t * (0.00275 * th3 - 0.00610 * th2 + 0.00317 * th)
+            + t * (-0.04214 * th3 + 0.08970 * th2 - 0.04153 * th + 0.00516)
+            + (0.15346 * th3 - 0.26756 * th2 + 0.06670 * th + 0.26688);
+
+        let mut s = sky {
+            sun_direction,
+            theta_s,
+            perez_Y,
+            perez_x,
+            perez_y,
+            zenith_Y,
+            zenith_x,
+            zenith_y,
+            sun_radiance: sun_radiance(theta_s, turbidity),
+            ground: color::new(),
+            intensity: 0.02,
+        };
+        if el < 0. {
+            s.sun_radiance = color::new();
+        }
+
+        // Ground reflects the irradiance from the sky dome and the sun
+        s.ground = ground_albedo * (s.irradiance() / PI);
+        s
+    }
+
+    pub fn with_intensity(mut self, intensity: f64) -> sky {
+        self.intensity = intensity;
+        self
+    }
+
+    // Disk light matching the sun drawn by `value`
+    pub fn sun(&self) -> light {
+        light::Sun(sun_light::from(
+            self.sun_direction,
+            self.intensity * self.sun_radiance,
+            SUN_ANGULAR_RADIUS,
+        ))
+    }
+
+    pub fn value(&self, direction: vec3, include_sun: bool) -> color {
+        let d = unit_vector(direction);
+        if d.y < 0. {
+            return self.intensity * self.ground;
+        }
+
+        let mut radiance = self.sky_radiance(d);
+        if include_sun && dot(d, self.sun_direction) >= SUN_ANGULAR_RADIUS.cos() {
+            radiance += self.sun_radiance;
+        }
+        self.intensity * radiance
+    }
+
+    fn sky_radiance(&self, d: vec3) -> color {
+        let cos_theta = d.y.max(1e-3);
+        let gamma = dot(d, self.sun_direction).clamp(-1., 1.).acos();
+
+        let Y = self.zenith_Y * perez(&self.perez_Y, cos_theta, gamma)
+            / perez(&self.perez_Y, 1., self.theta_s);
+        let x = self.zenith_x * perez(&self.perez_x, cos_theta, gamma)
+            / perez(&self.perez_x, 1., self.theta_s);
+        let y = self.zenith_y * perez(&self.perez_y, cos_theta, gamma)
+            / perez(&self.perez_y, 1., self.theta_s);
+
+        if y <= 0. {
+            return color::new();
+        }
+        let rgb = xyz_to_rgb(x / y * Y, Y, (1. - x - y) / y * Y);
+        color::from(rgb.x.max(0.), rgb.y.max(0.), rgb.z.max(0.))
+    }
+
+    fn irradiance(&self) -> color {
+        // Integrate the sky dome over the upper hemisphere on a coarse grid
+        let n_theta = 16;
+        let n_phi = 32;
+        let d_theta = PI / 2. / n_theta as f64;
+        let d_phi = 2. * PI / n_phi as f64;
+
+        let mut e = color::new();
+        for i in 0..n_theta {
+            let theta = (i as f64 + 0.5) * d_theta;
+            for j in 0..n_phi {
+                let phi = (j as f64 + 0.5) * d_phi;
+                let d = vec3::from(
+                    theta.sin() * phi.cos(),
+                    theta.cos(),
+                    theta.sin() * phi.sin(),
+                );
+                e += self.sky_radiance(d) * (theta.cos() * theta.sin() * d_theta * d_phi);
+            }
+        }
+
+        let solid_angle = 2. * PI * (1. - SUN_ANGULAR_RADIUS.cos());
+        e + self.sun_radiance * (solid_angle * self.sun_direction.y.max(0.))
+    }
+}
+
+fn perez(c: &[f64; 5], cos_theta: f64, gamma: f64) -> f64 {
+    (1. + c[0] * (c[1] / cos_theta).exp())
+        * (1. + c[2] * (c[3] * gamma).exp() + c[4] * gamma.cos() * gamma.cos())
+}
+
+fn sun_radiance(theta_s: f64, turbidity: f64) -> color {
+    // Relative optical mass of the atmosphere along the sun direction (Kasten 1966)
+    let theta_deg = theta_s.to_degrees();
+    let m = 1. / (theta_s.cos() + 0.15 * (93.885 - theta_deg).powf(-1.253));
+
+    // Rayleigh and aerosol (Angstrom) extinction at representative RGB wavelengths
+    let beta = 0.04608 * turbidity - 0.04586;
+    let transmittance = |lambda_um: f64| {
+        let tau_rayleigh = 0.008735 * lambda_um.powf(-4.08);
+        let tau_aerosol = beta * lambda_um.powf(-1.3);
+        (-m * (tau_rayleigh + tau_aerosol)).exp()
+    };
+
+    SUN_LUMINANCE
+        * color::from(
+            transmittance(0.68),
+            transmittance(0.55),
+            transmittance(0.44),
+        )
+}
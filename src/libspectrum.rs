@@ -0,0 +1,146 @@
+use std::sync::OnceLock;
+
+use crate::{
+    libcolor::xyz_to_rgb,
+    libvec::{color, vec3},
+};
+
+// Sampled range of visible wavelengths in nm
+pub const LAMBDA_MIN: f64 = 380.;
+pub const LAMBDA_MAX: f64 = 780.;
+
+// Hero wavelength sampling: one uniform wavelength plus two equally spaced companions,
+// carried in the three channels of a color
+pub fn sample_wavelengths() -> vec3 {
+    let range = LAMBDA_MAX - LAMBDA_MIN;
+    let hero = rand::random::<f64>() * range;
+    let rotate = |k: f64| LAMBDA_MIN + (hero + k * range / 3.) % range;
+    vec3::from(rotate(0.), rotate(1.), rotate(2.))
+}
+
+// Convert an RGB value to the spectrum sampled at `lambda`. RGB rendering is signalled by
+// zero wavelengths, in which case the color is returned unchanged. Wavelengths dropped by
+// dispersion take the value at the hero wavelength, the integrator gives them no weight.
+pub fn spectral(c: color, lambda: vec3) -> color {
+    if lambda.x == 0. {
+        return c;
+    }
+    let at = |l: f64| upsample(c, if l > 0. { l } else { lambda.x });
+    color::from(at(lambda.x), at(lambda.y), at(lambda.z))
+}
+
+fn upsample(c: color, lambda: f64) -> f64 {
+    // Smooth basis functions for blue, green and red that sum to one everywhere,
+    // so a white albedo stays a constant spectrum
+    let blue = 1. - smoothstep(470., 530., lambda);
+    let red = smoothstep(560., 620., lambda);
+    let green = 1. - blue - red;
+    c.x * red + c.y * green + c.z * blue
+}
+
+fn smoothstep(edge0: f64, edge1: f64, x: f64) -> f64 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0., 1.);
+    t * t * (3. - 2. * t)
+}
+
+// CIE 1931 color matching functions, multi-lobe fit by Wyman, Sloan and Shirley (2013)
+pub fn cie_xyz(lambda: f64) -> (f64, f64, f64) {
+    let g = |mu: f64, sigma1: f64, sigma2: f64| {
+        let t = (lambda - mu) / if lambda < mu { sigma1 } else { sigma2 };
+        (-0.5 * t * t).exp()
+    };
+
+    let x =
+        1.056 * g(599.8, 37.9, 31.0) + 0.362 * g(442.0, 16.0, 26.7) - 0.065 * g(501.1, 20.4, 26.2);
+    let y = 0.821 * g(568.8, 46.9, 40.5) + 0.286 * g(530.9, 16.3, 31.1);
+    let z = 1.217 * g(437.0, 11.8, 36.0) + 0.681 * g(459.0, 26.0, 13.8);
+    (x, y, z)
+}
+
+// Normalization so that a constant unit spectrum maps to RGB white
+fn white_balance() -> color {
+    static WHITE: OnceLock<color> = OnceLock::new();
+    *WHITE.get_or_init(|| {
+        let (mut x, mut y, mut z) = (0., 0., 0.);
+        let steps = 400;
+        let d_lambda = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        for i in 0..steps {
+            let (cx, cy, cz) = cie_xyz(LAMBDA_MIN + (i as f64 + 0.5) * d_lambda);
+            x += cx * d_lambda;
+            y += cy * d_lambda;
+            z += cz * d_lambda;
+        }
+        xyz_to_rgb(x, y, z)
+    })
+}
+
+// Convert radiance sampled at `lambda` to linear sRGB through CIE XYZ
+pub fn to_rgb(l: color, lambda: vec3) -> color {
+    // Each wavelength was drawn uniformly, so the pdf is 1/range per sample
+    let weight = (LAMBDA_MAX - LAMBDA_MIN) / 3.;
+    let (mut x, mut y, mut z) = (0., 0., 0.);
+    for i in 0..3 {
+        if lambda[i] == 0. {
+            continue;
+        }
+        let (cx, cy, cz) = cie_xyz(lambda[i]);
+        x += l[i] * cx * weight;
+        y += l[i] * cy * weight;
+        z += l[i] * cz * weight;
+    }
+
+    let white = white_balance();
+    let rgb = xyz_to_rgb(x, y, z);
+    color::from(rgb.x / white.x, rgb.y / white.y, rgb.z / white.z)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Average of to_rgb over stratified hero wavelengths, the estimate the renderer converges to
+    fn round_trip(c: color) -> color {
+        let n = 3000;
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let mut sum = color::new();
+        for i in 0..n {
+            let hero = (i as f64 + 0.5) / n as f64 * range;
+            let rotate = |k: f64| LAMBDA_MIN + (hero + k * range / 3.) % range;
+            let lambda = vec3::from(rotate(0.), rotate(1.), rotate(2.));
+            sum += to_rgb(spectral(c, lambda), lambda);
+        }
+        sum / n as f64
+    }
+
+    #[test]
+    fn constant_spectrum_converts_back_to_white() {
+        let rgb = round_trip(color::from(1., 1., 1.));
+        for i in 0..3 {
+            assert!((rgb[i] - 1.).abs() < 1e-3, "channel {} is {}", i, rgb[i]);
+        }
+    }
+
+    #[test]
+    fn saturated_primaries_round_trip() {
+        // The smooth basis spills a little into the neighbouring channels
+        let primaries = [
+            color::from(1., 0., 0.),
+            color::from(0., 1., 0.),
+            color::from(0., 0., 1.),
+        ];
+        for c in primaries {
+            let rgb = round_trip(c);
+            for i in 0..3 {
+                assert!(
+                    (rgb[i] - c[i]).abs() < 0.1,
+                    "{} {} {} channel {} is {}",
+                    c.x,
+                    c.y,
+                    c.z,
+                    i,
+                    rgb[i]
+                );
+            }
+        }
+    }
+}
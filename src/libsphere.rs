@@ -1,6 +1,8 @@
 use std::sync::Arc;
 
-use crate::{libhittable::hit_record, libmaterial::material, libray::ray, libvec::*};
+use crate::{
+    libaabb::aabb, libhittable::hit_record, libmaterial::material, libray::ray, libvec::*,
+};
 
 pub struct sphere {
     pub center: point3,
@@ -19,7 +21,20 @@ impl sphere {
 
     #[inline(always)]
     pub fn hit(&self, r: ray, t_min: f64, t_max: f64, rec: &mut hit_record) -> bool {
-        let oc = r.origin - self.center;
+        self.hit_at(self.center, r, t_min, t_max, rec)
+    }
+
+    // Intersect the sphere as if it were centered at `center`, used by moving spheres
+    #[inline(always)]
+    pub fn hit_at(
+        &self,
+        center: point3,
+        r: ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut hit_record,
+    ) -> bool {
+        let oc = r.origin - center;
         let a = r.direction.length_squared();
         let half_b = dot(oc, r.direction);
         let c = oc.length_squared() - self.radius * self.radius;
@@ -35,26 +50,42 @@ impl sphere {
             if root < t_min || t_max < root {
                 root = (-half_b + sqrtd) / a;
                 if root < t_min || t_max < root {
-                    false
-                } else {
-                    // not clean code (repeating)
-                    rec.t = root;
-                    rec.p = r.at(rec.t);
-                    let outward_normal = (rec.p - self.center) / self.radius;
-                    rec.set_face_normal(r, outward_normal);
-                    rec.mat = self.m.clone();
-
-                    true
+                    return false;
                 }
-            } else {
-                rec.t = root;
-                rec.p = r.at(rec.t);
-                let outward_normal = (rec.p - self.center) / self.radius;
-                rec.set_face_normal(r, outward_normal);
-                rec.mat = self.m.clone();
-
-                true
             }
+
+            rec.t = root;
+            rec.p = r.at(rec.t);
+            let outward_normal = (rec.p - center) / self.radius;
+            rec.set_face_normal(r, outward_normal);
+            rec.mat = self.m.clone();
+            self.set_uv(outward_normal, rec);
+
+            true
         }
     }
+
+    pub fn bounding_box(&self) -> aabb {
+        let r = vec3::from(self.radius, self.radius, self.radius);
+        aabb::from(self.center - r, self.center + r)
+    }
+
+    fn set_uv(&self, p: point3, rec: &mut hit_record) {
+        // p: a given point on the unit sphere centered at the origin
+        // u: angle around the Y axis from X=-1, v: angle from Y=-1 to Y=+1, both in [0, 1]
+        let pi = std::f64::consts::PI;
+        let theta = (-p.y).clamp(-1., 1.).acos();
+        let phi = (-p.z).atan2(p.x) + pi;
+
+        rec.u = phi / (2. * pi);
+        rec.v = theta / pi;
+        rec.dpdu = 2. * pi * self.radius * vec3::from(p.z, 0., -p.x);
+        rec.dpdv = pi
+            * self.radius
+            * vec3::from(
+                -phi.cos() * theta.cos(),
+                theta.sin(),
+                phi.sin() * theta.cos(),
+            );
+    }
 }
@@ -0,0 +1,241 @@
+use std::sync::Arc;
+
+use crate::{
+    libhittable::{hit_record, scatter_record},
+    libmaterial::{lambertian, material},
+    libmicrofacet::{fresnel_dielectric, refract_local},
+    libray::ray,
+    libspectrum::spectral,
+    libvec::{color, dot, random_unit_vector, reflect, unit_vector, vec3},
+};
+
+// Scattering events before a walk is considered absorbed
+const MAX_WALK_STEPS: i32 = 256;
+
+// Random walk subsurface scattering inside a closed shape with a smooth dielectric surface.
+// Light refracted into the surface scatters isotropically through the object it hit until
+// it finds its way out, then leaves through a diffuse exit lobe.
+pub struct subsurface {
+    albedo: color,  // single scattering albedo per channel
+    sigma_t: color, // extinction, the inverse of the mean free path
+    ior: f64,
+    exit_lobe: Arc<material>, // white diffuse lobe evaluated for light sampling at the exit
+}
+
+impl subsurface {
+    pub fn from(albedo: color, mean_free_path: color, ior: f64) -> subsurface {
+        let inverse = |d: f64| 1. / d.max(1e-6);
+        subsurface {
+            albedo,
+            sigma_t: color::from(
+                inverse(mean_free_path.x),
+                inverse(mean_free_path.y),
+                inverse(mean_free_path.z),
+            ),
+            ior,
+            exit_lobe: Arc::new(material::Lambertian(lambertian::from(color::from(
+                1., 1., 1.,
+            )))),
+        }
+    }
+
+    pub fn scatter(&self, r_in: &ray, rec: &hit_record, srec: &mut scatter_record) -> bool {
+        // The walk is traced against the object actually hit, at the time of the ray
+        let object = match rec.object {
+            Some(object) => object,
+            None => return false,
+        };
+
+        let mut dir = unit_vector(r_in.direction);
+        if !rec.front_face {
+            // Started inside through numerical error, let the ray carry on
+            srec.attenuation = color::from(1., 1., 1.);
+            srec.scattered = r_in.spawn(rec.p, dir);
+            srec.is_specular = true;
+            return true;
+        }
+
+        // Reflect off the surface or refract into the volume
+        let cos_theta = dot(-dir, rec.normal);
+        dir = match refract_local(-dir, rec.normal, self.ior) {
+            Some(d) if rand::random::<f64>() >= fresnel_dielectric(cos_theta, self.ior) => d,
+            _ => {
+                srec.attenuation = color::from(1., 1., 1.);
+                srec.scattered = r_in.spawn(rec.p, reflect(&dir, &rec.normal));
+                srec.is_specular = true;
+                return true;
+            }
+        };
+
+        // Medium properties at the sampled wavelengths
+        let albedo = spectral(self.albedo, r_in.lambda);
+        let sigma_t = spectral(self.sigma_t, r_in.lambda);
+        let transmittance = |distance: f64| {
+            color::from(
+                (-sigma_t.x * distance).exp(),
+                (-sigma_t.y * distance).exp(),
+                (-sigma_t.z * distance).exp(),
+            )
+        };
+
+        let mut p = rec.p;
+        let mut throughput = color::from(1., 1., 1.);
+        for _ in 0..MAX_WALK_STEPS {
+            let mut exit = hit_record::new();
+            if !object.hit(r_in.spawn(p, dir), 1e-4, f64::INFINITY, &mut exit) {
+                return false;
+            }
+
+            // Sample a free flight distance in one channel, weighted over all three
+            let channel = ((rand::random::<f64>() * 3.) as i32).min(2);
+            let distance = -(1. - rand::random::<f64>()).ln() / sigma_t[channel];
+
+            if distance < exit.t {
+                let tr = transmittance(distance);
+                let sigma_s = albedo * sigma_t;
+                let pdf = dot(sigma_t, tr) / 3.;
+                throughput = throughput * sigma_s * tr / pdf;
+                p += distance * dir;
+                dir = random_unit_vector();
+                continue;
+            }
+
+            let tr = transmittance(exit.t);
+            let pdf = (tr.x + tr.y + tr.z) / 3.;
+            throughput = throughput * tr / pdf;
+
+            // Leave the surface or get reflected back inside
+            let cos_theta = dot(-dir, exit.normal);
+            match refract_local(-dir, exit.normal, 1. / self.ior) {
+                Some(_)
+                    if rand::random::<f64>() >= fresnel_dielectric(cos_theta, 1. / self.ior) =>
+                {
+                    // The hit normal faces back inside, lights are connected at the exit
+                    let outward = -exit.normal;
+                    let mut out = outward + random_unit_vector();
+                    if out.near_zero() {
+                        out = outward;
+                    }
+                    srec.attenuation = throughput;
+                    srec.scattered = r_in.spawn(exit.p, out);
+                    srec.is_specular = false;
+                    srec.exit = Some(hit_record {
+                        p: exit.p,
+                        normal: outward,
+                        front_face: true,
+                        mat: self.exit_lobe.clone(),
+                        ..hit_record::new()
+                    });
+                    return true;
+                }
+                _ => {
+                    dir = reflect(&dir, &exit.normal);
+                    p = exit.p;
+                }
+            }
+        }
+        false
+    }
+
+    // Only the delta reflection leaves at the entry point, light below the surface is
+    // connected at the exit vertex of the walk instead
+    pub fn eval(&self, _r_in: &ray, _rec: &hit_record, _wi: vec3) -> color {
+        color::new()
+    }
+}
+
+#[macro_export]
+macro_rules! subsurface {
+    ($albedo:expr, $mean_free_path:expr, $ior:expr) => {
+        std::sync::Arc::new(material::Subsurface(subsurface::from(
+            $albedo,
+            $mean_free_path,
+            $ior,
+        )))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libhittable::{hittable, scatter};
+    use crate::libsphere::sphere;
+    use crate::libvec::point3;
+
+    // Light arriving off-centre at a unit sphere made of `mat`
+    fn unit_sphere(mat: &Arc<material>) -> (ray, hittable) {
+        let r_in = ray::from(point3::from(0.3, 0.2, 5.), vec3::from(0., 0., -1.));
+        (r_in, crate::sphere!(0., 0., 0., 1., mat))
+    }
+
+    #[test]
+    fn lossless_walks_conserve_energy() {
+        let mat: Arc<material> =
+            crate::subsurface!(color::from(1., 1., 1.), color::from(100., 100., 100.), 1.5);
+        let (r_in, ball) = unit_sphere(&mat);
+        let mut rec = hit_record::new();
+        assert!(ball.hit_object(r_in, 1e-3, f64::INFINITY, &mut rec));
+
+        let n = 20_000;
+        let mut total = 0.;
+        for _ in 0..n {
+            let mut srec = scatter_record::new();
+            if mat.scatter(&r_in, &rec, &mut srec) {
+                total += srec.attenuation.x;
+            }
+        }
+        let albedo = total / n as f64;
+        assert!(
+            (0.99..1.01).contains(&albedo),
+            "white medium returned {}",
+            albedo
+        );
+    }
+
+    #[test]
+    fn walks_leave_through_the_surface() {
+        let mat: Arc<material> =
+            crate::subsurface!(color::from(0.8, 0.8, 0.8), color::from(0.3, 0.3, 0.3), 1.5);
+        let (r_in, ball) = unit_sphere(&mat);
+        let mut rec = hit_record::new();
+        assert!(ball.hit_object(r_in, 1e-3, f64::INFINITY, &mut rec));
+
+        let mut exits = 0;
+        for _ in 0..2_000 {
+            let mut srec = scatter_record::new();
+            if !mat.scatter(&r_in, &rec, &mut srec) || srec.is_specular {
+                continue;
+            }
+            let exit = srec
+                .exit
+                .as_ref()
+                .expect("diffuse exits record their vertex");
+            let outward = exit.p / exit.p.length();
+            assert!((exit.p.length() - 1.).abs() < 1e-6, "exit off the surface");
+            assert!(
+                dot(exit.normal, outward) > 0.999,
+                "exit normal faces inside"
+            );
+            assert!((srec.scattered.origin - exit.p).length() < 1e-9);
+            assert!(
+                dot(srec.scattered.direction, outward) > 0.,
+                "exit heads inside"
+            );
+            exits += 1;
+        }
+        assert!(exits > 1_000, "only {} walks left the sphere", exits);
+    }
+
+    #[test]
+    fn hits_without_an_object_are_absorbed() {
+        let mat: Arc<material> =
+            crate::subsurface!(color::from(0.8, 0.8, 0.8), color::from(0.3, 0.3, 0.3), 1.5);
+        let (r_in, ball) = unit_sphere(&mat);
+        let mut rec = hit_record::new();
+        assert!(ball.hit(r_in, 1e-3, f64::INFINITY, &mut rec));
+        assert!(rec.object.is_none());
+
+        let mut srec = scatter_record::new();
+        assert!(!mat.scatter(&r_in, &rec, &mut srec));
+    }
+}
@@ -0,0 +1,163 @@
+use std::io::{self, Read};
+
+use crate::{
+    libperlin::perlin,
+    libvec::{color, point3},
+};
+
+pub enum texture {
+    Solid(color),
+    Image(image_texture),
+    Noise(noise_texture),
+}
+
+impl texture {
+    pub fn value(&self, u: f64, v: f64, p: point3) -> color {
+        match self {
+            texture::Solid(c) => *c,
+            texture::Image(i) => i.value(u, v),
+            texture::Noise(n) => n.value(p),
+        }
+    }
+}
+
+// Image texture with values in [0, 1], stored as they appear in the file
+pub struct image_texture {
+    width: usize,
+    height: usize,
+    data: Vec<color>,
+}
+
+impl image_texture {
+    pub fn from(width: usize, height: usize, data: Vec<color>) -> image_texture {
+        assert_eq!(data.len(), width * height, "image size does not match data");
+        image_texture {
+            width,
+            height,
+            data,
+        }
+    }
+
+    // Load an ASCII (P3) or binary (P6) PPM image
+    pub fn load(path: &str) -> io::Result<image_texture> {
+        let mut bytes = Vec::new();
+        std::fs::File::open(path)?.read_to_end(&mut bytes)?;
+
+        let invalid = |msg: &str| io::Error::new(io::ErrorKind::InvalidData, msg.to_string());
+        let mut pos = 0;
+        let mut header = Vec::new();
+        while header.len() < 4 {
+            let token = next_token(&bytes, &mut pos).ok_or_else(|| invalid("truncated header"))?;
+            header.push(token);
+        }
+
+        let parse = |s: &str| s.parse::<usize>().map_err(|_| invalid("bad header"));
+        let width = parse(&header[1])?;
+        let height = parse(&header[2])?;
+        let max_value = parse(&header[3])? as f64;
+        if max_value == 0. {
+            return Err(invalid("max value must be positive"));
+        }
+        // 16 bit data takes two bytes a sample, so that size must fit too
+        let count = width
+            .checked_mul(height)
+            .and_then(|n| n.checked_mul(3))
+            .filter(|n| n.checked_mul(2).is_some())
+            .ok_or_else(|| invalid("image dimensions too large"))?;
+
+        let samples: Vec<f64> = match header[0].as_str() {
+            "P3" => (0..count)
+                .map(|_| {
+                    next_token(&bytes, &mut pos)
+                        .and_then(|t| t.parse::<f64>().ok())
+                        .ok_or_else(|| invalid("truncated data"))
+                })
+                .collect::<io::Result<_>>()?,
+            "P6" => {
+                // A single whitespace byte separates the header from the data
+                let start = pos + 1;
+                if max_value < 256. {
+                    bytes
+                        .get(start..)
+                        .and_then(|data| data.get(..count))
+                        .ok_or_else(|| invalid("truncated data"))?
+                        .iter()
+                        .map(|&b| b as f64)
+                        .collect()
+                } else {
+                    bytes
+                        .get(start..)
+                        .and_then(|data| data.get(..2 * count))
+                        .ok_or_else(|| invalid("truncated data"))?
+                        .chunks_exact(2)
+                        .map(|b| u16::from_be_bytes([b[0], b[1]]) as f64)
+                        .collect()
+                }
+            }
+            _ => return Err(invalid("not a PPM image")),
+        };
+
+        let data = samples
+            .chunks_exact(3)
+            .map(|c| color::from(c[0], c[1], c[2]) / max_value)
+            .collect();
+        Ok(image_texture::from(width, height, data))
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    pub fn value(&self, u: f64, v: f64) -> color {
+        if self.width == 0 || self.height == 0 {
+            return color::from(0., 1., 1.);
+        }
+
+        // Flip v so that v = 1 is the top row of the image
+        let u = u.clamp(0., 1.);
+        let v = 1. - v.clamp(0., 1.);
+        let i = ((u * self.width as f64) as usize).min(self.width - 1);
+        let j = ((v * self.height as f64) as usize).min(self.height - 1);
+        self.data[j * self.width + i]
+    }
+}
+
+fn next_token(bytes: &[u8], pos: &mut usize) -> Option<String> {
+    // Skip whitespace and comments
+    loop {
+        match bytes.get(*pos)? {
+            b'#' => {
+                while *bytes.get(*pos)? != b'\n' {
+                    *pos += 1;
+                }
+            }
+            b if b.is_ascii_whitespace() => *pos += 1,
+            _ => break,
+        }
+    }
+
+    let start = *pos;
+    while bytes.get(*pos).is_some_and(|b| !b.is_ascii_whitespace()) {
+        *pos += 1;
+    }
+    Some(String::from_utf8_lossy(&bytes[start..*pos]).into_owned())
+}
+
+pub struct noise_texture {
+    noise: perlin,
+    scale: f64,
+}
+
+impl noise_texture {
+    pub fn from(scale: f64) -> noise_texture {
+        noise_texture {
+            noise: perlin::new(),
+            scale,
+        }
+    }
+
+    pub fn value(&self, p: point3) -> color {
+        let n = 0.5 * (1. + self.noise.noise(self.scale * p));
+        color::from(n, n, n)
+    }
+}
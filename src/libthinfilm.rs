@@ -0,0 +1,204 @@
+use std::ops::{Add, Div, Mul, Sub};
+
+use crate::libvec::{color, vec3};
+
+// Representative wavelengths in nm for the red, green and blue channels
+const RGB_WAVELENGTHS: [f64; 3] = [650., 532., 450.];
+
+#[derive(Debug, Clone, Copy)]
+pub struct complex {
+    pub re: f64,
+    pub im: f64,
+}
+
+impl complex {
+    pub fn from(re: f64, im: f64) -> complex {
+        complex { re, im }
+    }
+
+    pub fn real(re: f64) -> complex {
+        complex { re, im: 0. }
+    }
+
+    pub fn norm_squared(self) -> f64 {
+        self.re * self.re + self.im * self.im
+    }
+
+    pub fn sqrt(self) -> complex {
+        // Principal square root
+        let r = self.norm_squared().sqrt();
+        let re = (0.5 * (r + self.re)).max(0.).sqrt();
+        let im = (0.5 * (r - self.re)).max(0.).sqrt();
+        complex::from(re, if self.im < 0. { -im } else { im })
+    }
+
+    pub fn exp(self) -> complex {
+        let m = self.re.exp();
+        complex::from(m * self.im.cos(), m * self.im.sin())
+    }
+}
+
+impl Add for complex {
+    type Output = complex;
+
+    fn add(self, rhs: complex) -> complex {
+        complex::from(self.re + rhs.re, self.im + rhs.im)
+    }
+}
+
+impl Sub for complex {
+    type Output = complex;
+
+    fn sub(self, rhs: complex) -> complex {
+        complex::from(self.re - rhs.re, self.im - rhs.im)
+    }
+}
+
+impl Mul for complex {
+    type Output = complex;
+
+    fn mul(self, rhs: complex) -> complex {
+        complex::from(
+            self.re * rhs.re - self.im * rhs.im,
+            self.re * rhs.im + self.im * rhs.re,
+        )
+    }
+}
+
+impl Div for complex {
+    type Output = complex;
+
+    fn div(self, rhs: complex) -> complex {
+        let d = rhs.norm_squared();
+        complex::from(
+            (self.re * rhs.re + self.im * rhs.im) / d,
+            (self.im * rhs.re - self.re * rhs.im) / d,
+        )
+    }
+}
+
+// A thin dielectric coating whose interference modulates the Fresnel reflectance
+#[derive(Clone, Copy)]
+pub struct thin_film {
+    pub thickness: f64, // in nm
+    pub ior: f64,
+}
+
+impl thin_film {
+    pub fn from(thickness: f64, ior: f64) -> thin_film {
+        thin_film { thickness, ior }
+    }
+
+    // Airy reflectance for light arriving from a medium with real IOR `n_incident` onto the
+    // film over a substrate with IOR `n_substrate` (eta + ik), per channel. Channels are the
+    // sampled wavelengths `lambda` in spectral mode and RGB otherwise.
+    pub fn reflectance(
+        &self,
+        cos_theta_i: f64,
+        n_incident: f64,
+        n_substrate: [complex; 3],
+        lambda: vec3,
+    ) -> color {
+        let wavelength = |i: usize| match lambda[i as i32] {
+            l if l > 0. => l,
+            _ if lambda.x > 0. => lambda.x, // dropped by dispersion
+            _ => RGB_WAVELENGTHS[i],
+        };
+        let r = |i: usize| self.airy(cos_theta_i, n_incident, n_substrate[i], wavelength(i));
+        color::from(r(0), r(1), r(2))
+    }
+
+    fn airy(&self, cos_theta_i: f64, n1: f64, n3: complex, lambda: f64) -> f64 {
+        let cos1 = complex::real(cos_theta_i.clamp(0., 1.));
+        let sin2_1 = 1. - cos_theta_i * cos_theta_i;
+        let n1c = complex::real(n1);
+        let n2c = complex::real(self.ior);
+
+        // Snell's law carried into the film and the substrate, complex past the critical angle
+        let one = complex::real(1.);
+        let cos2 = (one - complex::real(sin2_1 * (n1 / self.ior) * (n1 / self.ior))).sqrt();
+        let ratio3 = n1c / n3;
+        let cos3 = (one - complex::real(sin2_1) * ratio3 * ratio3).sqrt();
+
+        // Round trip phase difference inside the film
+        let phase = 4. * std::f64::consts::PI / lambda * self.thickness;
+        let delta = n2c * cos2 * complex::real(phase);
+        let shift = (complex::from(0., 1.) * delta).exp();
+
+        let airy = |r12: complex, r23: complex| {
+            let r = (r12 + r23 * shift) / (one + r12 * r23 * shift);
+            r.norm_squared()
+        };
+
+        let rs12 = (n1c * cos1 - n2c * cos2) / (n1c * cos1 + n2c * cos2);
+        let rs23 = (n2c * cos2 - n3 * cos3) / (n2c * cos2 + n3 * cos3);
+        let rp12 = (n2c * cos1 - n1c * cos2) / (n2c * cos1 + n1c * cos2);
+        let rp23 = (n3 * cos2 - n2c * cos3) / (n3 * cos2 + n2c * cos3);
+
+        // Unpolarized light averages both polarizations
+        0.5 * (airy(rs12, rs23) + airy(rp12, rp23))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        libmicrofacet::{fresnel_conductor, fresnel_dielectric},
+        libspectrum::LAMBDA_MIN,
+    };
+
+    // Gold at the RGB wavelengths
+    fn gold() -> [complex; 3] {
+        [
+            complex::from(0.18, 3.42),
+            complex::from(0.42, 2.35),
+            complex::from(1.37, 1.77),
+        ]
+    }
+
+    #[test]
+    fn zero_thickness_is_the_bare_substrate() {
+        let bare = thin_film::from(0., 1.33);
+        let g = gold();
+        let eta = color::from(g[0].re, g[1].re, g[2].re);
+        let k = color::from(g[0].im, g[1].im, g[2].im);
+        for a in 0..=10 {
+            let cos_theta = a as f64 / 10.;
+            let film = bare.reflectance(cos_theta, 1., g, vec3::new());
+            let fresnel = fresnel_conductor(cos_theta, eta, k);
+            for i in 0..3 {
+                assert!((film[i] - fresnel[i]).abs() < 1e-9, "cos {}", cos_theta);
+            }
+
+            let glass = bare.reflectance(cos_theta, 1., [complex::real(1.5); 3], vec3::new());
+            assert!((glass.x - fresnel_dielectric(cos_theta, 1.5)).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn reflectance_stays_in_unit_range_across_wavelengths() {
+        let films = [thin_film::from(300., 1.33), thin_film::from(500., 2.4)];
+        let substrates = [gold(), [complex::real(1.5); 3], [complex::real(3.); 3]];
+        for film in films {
+            for substrate in substrates {
+                for a in 0..=10 {
+                    let cos_theta = a as f64 / 10.;
+                    let (mut lo, mut hi) = (1f64, 0f64);
+                    for l in 0..=400 {
+                        let lambda = LAMBDA_MIN + l as f64;
+                        let r = film.reflectance(
+                            cos_theta,
+                            1.,
+                            substrate,
+                            vec3::from(lambda, lambda, lambda),
+                        );
+                        lo = lo.min(r.x);
+                        hi = hi.max(r.x);
+                    }
+                    assert!(lo >= 0. && hi <= 1. + 1e-12, "{} to {}", lo, hi);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,217 @@
+use crate::{
+    libaabb::{aabb, surrounding_box},
+    libanimation::{interpolation, keyframes},
+    libhittable::{hit_record, hittable},
+    libray::ray,
+    libvec::{degrees_to_radians, rotate, vec3},
+};
+
+// Instance of an object scaled, rotated and then displaced, each of which can change over
+// time. Media keep their look as they grow, their density being per unit of object space.
+pub struct transform {
+    pub object: Box<hittable>,
+    offset: keyframes<vec3>,
+    rotation: keyframes<vec3>, // Euler angles in degrees, about x, then y, then z
+    scale: keyframes<f64>,     // uniform and positive
+}
+
+impl transform {
+    pub fn from(object: hittable, offset: vec3) -> transform {
+        transform::animated(object, keyframes::constant(offset))
+    }
+
+    // Offset moving linearly from offset0 at time0 to offset1 at time1
+    pub fn moving(
+        object: hittable,
+        offset0: vec3,
+        offset1: vec3,
+        time0: f64,
+        time1: f64,
+    ) -> transform {
+        let offset = keyframes::from(interpolation::Linear)
+            .with_key(time0, offset0)
+            .with_key(time1, offset1);
+        transform::animated(object, offset)
+    }
+
+    // Keyframed position, without rotation or scale until they are added
+    pub fn animated(object: hittable, offset: keyframes<vec3>) -> transform {
+        transform {
+            object: Box::new(object),
+            offset,
+            rotation: keyframes::constant(vec3::new()),
+            scale: keyframes::constant(1.),
+        }
+    }
+
+    pub fn with_rotation(mut self, rotation: keyframes<vec3>) -> transform {
+        self.rotation = rotation;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: keyframes<f64>) -> transform {
+        self.scale = scale;
+        self
+    }
+
+    pub fn offset(&self, time: f64) -> vec3 {
+        self.offset.at(time)
+    }
+
+    // Move the ray into object space instead of moving the object. The direction is scaled
+    // along with the origin so that hits keep their distance along the ray.
+    fn to_object(&self, r: ray) -> ray {
+        let rotation = self.rotation.at(r.time);
+        let scale = self.scale.at(r.time);
+        let mut moved = r;
+        moved.origin = unrotate_euler(r.origin - self.offset(r.time), rotation) / scale;
+        moved.direction = unrotate_euler(r.direction, rotation) / scale;
+        moved
+    }
+
+    // Bring a hit found in object space back into the world
+    fn to_world(&self, rec: &mut hit_record, time: f64) {
+        let rotation = self.rotation.at(time);
+        let scale = self.scale.at(time);
+        rec.p = rotate_euler(scale * rec.p, rotation) + self.offset(time);
+        rec.normal = rotate_euler(rec.normal, rotation);
+        rec.dpdu = rotate_euler(scale * rec.dpdu, rotation);
+        rec.dpdv = rotate_euler(scale * rec.dpdv, rotation);
+    }
+
+    pub fn hit<'a>(&'a self, r: ray, t_min: f64, t_max: f64, rec: &mut hit_record<'a>) -> bool {
+        if !self.object.hit(self.to_object(r), t_min, t_max, rec) {
+            return false;
+        }
+        self.to_world(rec, r.time);
+        true
+    }
+
+    pub fn hit_surface<'a>(
+        &'a self,
+        r: ray,
+        t_min: f64,
+        t_max: f64,
+        rec: &mut hit_record<'a>,
+    ) -> bool {
+        if !self
+            .object
+            .hit_surface(self.to_object(r), t_min, t_max, rec)
+        {
+            return false;
+        }
+        self.to_world(rec, r.time);
+        true
+    }
+
+    pub fn transmittance(&self, r: ray, t_min: f64, t_max: f64) -> f64 {
+        self.object.transmittance(self.to_object(r), t_min, t_max)
+    }
+
+    pub fn bounding_box(&self, time0: f64, time1: f64) -> Option<aabb> {
+        let mut inner = self.object.bounding_box(time0, time1)?;
+
+        // A rotated object stays within the sphere about its origin through the farthest corner
+        let rotation = self.rotation.control_points(time0, time1);
+        if rotation.iter().any(|angles| !angles.near_zero()) {
+            let far = |a: i32| inner.minimum[a].abs().max(inner.maximum[a].abs());
+            let radius = vec3::from(far(0), far(1), far(2)).length();
+            let reach = vec3::from(radius, radius, radius);
+            inner = aabb::from(-reach, reach);
+        }
+
+        // Scale and offset stay within the hulls of the control points of their curves, so
+        // every point does within the boxes of all their pairings
+        let scales = self.scale.control_points(time0, time1);
+        self.offset
+            .control_points(time0, time1)
+            .into_iter()
+            .flat_map(|offset| {
+                scales.iter().map(move |&scale| {
+                    aabb::from(scale * inner.minimum, scale * inner.maximum).translate(offset)
+                })
+            })
+            .reduce(surrounding_box)
+    }
+}
+
+// Rotate by Euler angles in degrees, about x first, then y, then z
+fn rotate_euler(v: vec3, angles: vec3) -> vec3 {
+    let v = rotate(v, vec3::from(1., 0., 0.), degrees_to_radians(angles.x));
+    let v = rotate(v, vec3::from(0., 1., 0.), degrees_to_radians(angles.y));
+    rotate(v, vec3::from(0., 0., 1.), degrees_to_radians(angles.z))
+}
+
+fn unrotate_euler(v: vec3, angles: vec3) -> vec3 {
+    let v = rotate(v, vec3::from(0., 0., 1.), -degrees_to_radians(angles.z));
+    let v = rotate(v, vec3::from(0., 1., 0.), -degrees_to_radians(angles.y));
+    rotate(v, vec3::from(1., 0., 0.), -degrees_to_radians(angles.x))
+}
+
+#[macro_export]
+macro_rules! transform {
+    ($object:expr, $offset:expr) => {
+        hittable::Transform(transform::from($object, $offset))
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::libmaterial::{lambertian, material};
+    use crate::libsphere::sphere;
+    use crate::libvec::color;
+
+    #[test]
+    fn box_holds_a_curved_path_between_keys() {
+        let mat = crate::lambertian!(0.5, 0.5, 0.5);
+        // Uneven keys make the curve overshoot the values at the keys
+        let offset = keyframes::from(interpolation::CatmullRom)
+            .with_key(0., vec3::from(0., 0., 0.))
+            .with_key(0.1, vec3::from(5., 0., 0.))
+            .with_key(1., vec3::from(0., 3., 0.))
+            .with_key(1.2, vec3::from(-4., 0., 1.));
+        let moved = transform::animated(
+            hittable::Sphere(sphere::from(vec3::new(), 1., &mat)),
+            offset,
+        );
+
+        let (time0, time1) = (0.05, 1.1);
+        let bbox = moved.bounding_box(time0, time1).unwrap();
+        for i in 0..=1000 {
+            let time = time0 + (time1 - time0) * i as f64 / 1000.;
+            let c = moved.offset(time);
+            for a in 0..3 {
+                assert!(bbox.minimum[a] <= c[a] - 1. + 1e-9);
+                assert!(bbox.maximum[a] >= c[a] + 1. - 1e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn rotation_and_scale_apply_about_the_object_origin() {
+        let mat = crate::lambertian!(0.5, 0.5, 0.5);
+        // A quarter turn about y takes +x to -z, then the sphere doubles in size
+        let moved = transform::from(
+            hittable::Sphere(sphere::from(vec3::from(1., 0., 0.), 1., &mat)),
+            vec3::from(0., 1., 0.),
+        )
+        .with_rotation(keyframes::constant(vec3::from(0., 90., 0.)))
+        .with_scale(keyframes::constant(2.));
+
+        let r = ray::from(vec3::from(0., 1., 10.), vec3::from(0., 0., -1.));
+        let mut rec = hit_record::new();
+        assert!(moved.hit(r, 0.001, f64::INFINITY, &mut rec));
+        assert!((rec.t - 10.).abs() < 1e-9);
+        assert!((rec.p - vec3::from(0., 1., 0.)).length() < 1e-9);
+        assert!((rec.normal - vec3::from(0., 0., 1.)).length() < 1e-9);
+
+        // The sphere now sits at (0, 1, -2) with radius 2
+        let bbox = moved.bounding_box(0., 1.).unwrap();
+        let (low, high) = (vec3::from(-2., -1., -4.), vec3::from(2., 3., 0.));
+        for a in 0..3 {
+            assert!(bbox.minimum[a] <= low[a] + 1e-9);
+            assert!(bbox.maximum[a] >= high[a] - 1e-9);
+        }
+    }
+}
@@ -198,35 +198,10 @@ pub fn random_unit_vector() -> vec3 {
     unit_vector(random_in_unit_sphere())
 }
 
-pub fn random_in_hemisphere(normal: vec3) -> vec3 {
-    let in_unit_sphere = random_in_unit_sphere();
-    if dot(in_unit_sphere, normal) > 0.0 {
-        // In the same hemisphere as the normal
-        in_unit_sphere
-    } else {
-        -in_unit_sphere
-    }
-}
-
 pub fn reflect(v: &vec3, n: &vec3) -> vec3 {
     *v - 2. * dot(*v, *n) * *n
 }
 
-pub fn refract(uv: &vec3, n: &vec3, etai_over_etat: f64) -> vec3 {
-    let cos_theta = min(dot(-*uv, *n), 1.);
-    let r_out_perp = etai_over_etat * (*uv + cos_theta * *n);
-    let r_out_parallel = -((1. - r_out_perp.length_squared()).abs()).sqrt() * *n;
-    r_out_perp + r_out_parallel
-}
-
-pub fn min(a: f64, b: f64) -> f64 {
-    if a < b {
-        a
-    } else {
-        b
-    }
-}
-
 pub fn random_in_unit_disk() -> vec3 {
     loop {
         let p = vec3::from(
@@ -241,3 +216,47 @@ pub fn random_in_unit_disk() -> vec3 {
         return p;
     }
 }
+
+// Rotate v about a unit axis by angle radians (Rodrigues' formula)
+pub fn rotate(v: vec3, axis: vec3, angle: f64) -> vec3 {
+    let (sin, cos) = angle.sin_cos();
+    cos * v + sin * cross(axis, v) + (1. - cos) * dot(axis, v) * axis
+}
+
+pub fn degrees_to_radians(degrees: f64) -> f64 {
+    degrees * std::f64::consts::PI / 180.
+}
+
+pub fn orthonormal_basis(n: vec3) -> (vec3, vec3) {
+    // Build two tangents perpendicular to the unit vector n (Duff et al. 2017)
+    let sign = if n.z >= 0. { 1. } else { -1. };
+    let a = -1. / (sign + n.z);
+    let b = n.x * n.y * a;
+    (
+        vec3::from(1. + sign * n.x * n.x * a, sign * b, -sign * n.x),
+        vec3::from(b, sign + n.y * n.y * a, -n.y),
+    )
+}
+
+// Orthonormal shading frame with n as the local z axis
+#[derive(Clone, Copy)]
+pub struct frame {
+    pub t: vec3,
+    pub b: vec3,
+    pub n: vec3,
+}
+
+impl frame {
+    pub fn from_normal(n: vec3) -> frame {
+        let (t, b) = orthonormal_basis(n);
+        frame { t, b, n }
+    }
+
+    pub fn to_local(self, v: vec3) -> vec3 {
+        vec3::from(dot(v, self.t), dot(v, self.b), dot(v, self.n))
+    }
+
+    pub fn to_world(self, v: vec3) -> vec3 {
+        v.x * self.t + v.y * self.b + v.z * self.n
+    }
+}
@@ -3,78 +3,88 @@
 #[global_allocator]
 static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
+mod libaabb;
+mod libanimation;
+mod libaperture;
+mod libbvh;
 mod libcamera;
 mod libcolor;
+mod libconstant_medium;
+mod libcrop_window;
+mod libheterogeneous_medium;
 mod libhittable;
 mod libhittable_list;
+mod liblayered;
+mod liblens;
+mod liblight;
+mod libmapped;
 mod libmaterial;
+mod libmicrofacet;
+mod libmix;
+mod libmoving_sphere;
+mod libperlin;
+mod libprincipled;
 mod libray;
+mod libscenes;
+mod libsky;
+mod libspectrum;
 mod libsphere;
+mod libsubsurface;
+mod libtexture;
+mod libthinfilm;
+mod libtransform;
 mod libvec;
 
-use libcamera::camera;
-use libcolor::write_color;
+use libaperture::aperture;
+use libcamera::{camera, projection, stereo_layout};
+use libcolor::{write_color, write_png};
+use libcrop_window::crop_window;
 use libhittable::scatter;
-use libhittable::{hit_record, hittable};
+use libhittable::{hit_record, scatter_record};
 use libhittable_list::hittable_list;
-use libmaterial::{dielectric, lambertian, material, metal};
+use liblens::lens_system;
 use libray::*;
-use libsphere::sphere;
+use libspectrum::{sample_wavelengths, spectral, to_rgb};
+use libtexture::image_texture;
 use libvec::*;
 
 use rayon::prelude::*;
 use std::io::{stderr, Write};
-use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicI32, Ordering};
 
-fn random_scene() -> hittable_list {
-    let mut world = hittable_list::new();
+// Playback rate of rendered image sequences
+const FRAMES_PER_SECOND: f64 = 24.;
+// Part of each frame the shutter stays open for, a 180 degree shutter
+const SHUTTER_FRACTION: f64 = 0.5;
+// Height of the view with --projection orthographic, in scene units
+const ORTHOGRAPHIC_HEIGHT: f64 = 6.;
+// Distance between the eyes with --stereo, in scene units
+const INTEROCULAR: f64 = 0.065;
+// Diagonal of a 36 by 24 mm film, for --lens prescriptions in millimetres
+const FULL_FRAME_DIAGONAL: f64 = 43.27;
 
-    let ground_material = lambertian!(0.5, 0.5, 0.5);
-    world.add(sphere!(0., -1000., 0., 1000., &ground_material));
+fn direct_lighting(r: &ray, rec: &hit_record, world: &hittable_list) -> color {
+    let mut direct = color::new();
 
-    for a in -11..11 {
-        for b in -11..11 {
-            let choose_mat = rand::random::<f64>();
-            let center = point3::from(
-                a as f64 + 0.9 * rand::random::<f64>(),
-                0.2,
-                b as f64 + 0.9 * rand::random::<f64>(),
-            );
+    for light in &world.lights {
+        if let Some(sample) = light.sample_li(rec.p) {
+            let f = rec.mat.eval(r, rec, sample.wi);
+            if f.near_zero() {
+                continue;
+            }
 
-            if (center - point3::from(4., 0.2, 0.)).length() > 0.9 {
-                if choose_mat < 0.4 {
-                    // diffuse
-                    let albedo = color::random() * color::random();
-                    let sphere_material = lambertian!(albedo);
-                    world.add(sphere!(center, 0.2, &sphere_material));
-                } else if choose_mat < 0.6 {
-                    // metal
-                    let albedo = color::random_range(0.5, 1.);
-                    let roughness = rand::random::<f64>();
-                    let sphere_material = metal!(albedo, roughness);
-                    world.add(sphere!(center, 0.2, &sphere_material));
-                } else {
-                    // glass
-                    let sphere_material = dielectric!(1.5);
-                    world.add(sphere!(center, 0.2, &sphere_material));
-                }
+            // Shadow ray toward the light, attenuated by any participating media
+            let shadow_ray = r.spawn(rec.p, sample.wi);
+            let tr = world.transmittance(shadow_ray, 0.001, sample.distance - 0.001);
+            if tr > 0. {
+                direct += tr * f * spectral(sample.li, r.lambda);
             }
         }
     }
-
-    let material1 = dielectric!(1.5);
-    world.add(sphere!(point3::from(0., 1., 0.), 1., &material1));
-
-    let material2 = lambertian!(0.4, 0.2, 0.1);
-    world.add(sphere!(point3::from(-4., 1., 0.), 1., &material2));
-
-    let material3 = metal!(color::from(0.7, 0.6, 0.5), 0.);
-    world.add(sphere!(point3::from(4., 1., 0.), 1., &material3));
-
-    world
+    direct
 }
 
-fn ray_color(r: ray, world: &hittable_list, depth: i32) -> color {
+fn ray_color(r: ray, world: &hittable_list, depth: i32, specular: bool) -> color {
     let mut rec = hit_record::new();
 
     if depth <= 0 {
@@ -82,21 +92,394 @@ fn ray_color(r: ray, world: &hittable_list, depth: i32) -> color {
     }
 
     if world.hit(r, 0.001, f64::INFINITY, &mut rec) {
-        let mut scattered = ray::new();
-        let mut attenuation = color::new();
-        if rec.mat.scatter(&r, &rec, &mut attenuation, &mut scattered) {
-            return attenuation * ray_color(scattered, world, depth - 1);
+        let mat = rec.mat.clone();
+        mat.perturb_normal(&mut rec);
+
+        let direct = direct_lighting(&r, &rec, world);
+
+        let mut srec = scatter_record::new();
+        if rec.mat.scatter(&r, &rec, &mut srec) {
+            let mut weight = srec.attenuation;
+            if r.lambda.y > 0. && srec.scattered.lambda.y == 0. {
+                // Dispersion dropped the secondary wavelengths, the hero carries all three
+                weight = weight * color::from(3., 0., 0.);
+            }
+
+            // Light is also connected where a subsurface walk leaves the object
+            let mut indirect = ray_color(srec.scattered, world, depth - 1, srec.is_specular);
+            if let Some(exit) = &srec.exit {
+                indirect += direct_lighting(&srec.scattered, exit, world);
+            }
+            return direct + weight * indirect;
         }
-        color::new()
+        direct
 
         // // let target = rec.p + rec.normal + random_in_unit_sphere(); // diffuse scattering
         // // let target = rec.p + rec.normal + random_unit_vector(); // lambertian scattering
         // let target = rec.p + random_in_hemisphere(rec.normal); // hemispherical scattering
         // 0.5 * ray_color(ray::from(rec.p, target-rec.p), world, depth-1)
+    } else if let Some(sky) = &world.sky {
+        // The sun disk is already accounted for by light sampling after diffuse bounces
+        spectral(sky.value(r.direction, specular), r.lambda)
     } else {
         let unit_direction = unit_vector(r.direction);
         let t = 0.5 * (unit_direction.y + 1.);
-        (1. - t) * color::from(1., 1., 1.) + t * color::from(0.5, 0.7, 1.)
+        spectral(
+            (1. - t) * color::from(1., 1., 1.) + t * color::from(0.5, 0.7, 1.),
+            r.lambda,
+        )
+    }
+}
+
+// Settings shared by every image rendered in one run
+struct render_settings {
+    image_width: i32,
+    image_height: i32,
+    samples_per_pixel: i32,
+    max_depth: i32,
+    spectral_mode: bool,
+    crop: crop_window, // region rendered, at the camera settings of the full frame
+    border: bool,      // place the region in a black frame of the full size
+}
+
+impl render_settings {
+    // Size of the written image
+    fn output_size(&self) -> (i32, i32) {
+        if self.border {
+            (self.image_width, self.image_height)
+        } else {
+            (self.crop.width(), self.crop.height())
+        }
+    }
+
+    // Turn rendered crop window pixels into the written image
+    fn output_pixels(&self, pixels: Vec<color>) -> Vec<color> {
+        if self.border {
+            self.crop
+                .composite(&pixels, self.image_width, self.image_height)
+        } else {
+            pixels
+        }
+    }
+}
+
+// Render the crop window into exposed pixel sums, top row first
+fn render(cam: &camera, world: &hittable_list, settings: &render_settings) -> Vec<color> {
+    let image_width = settings.image_width;
+    let image_height = settings.image_height;
+    let crop = settings.crop;
+    let num_pixels = crop.height() * crop.width();
+    let rows_done = AtomicI32::new(0);
+    let exposure = cam
+        .exposure_scale()
+        .expect("film speeds are checked for an f-number when setting up the camera");
+
+    (crop.y0..crop.y1)
+        .into_par_iter()
+        .flat_map_iter(|y| {
+            // Rows are counted from the bottom of the full frame
+            let j = image_height - 1 - y;
+            let row: Vec<color> = (crop.x0..crop.x1)
+                .map(|i| {
+                    let mut pixel_color = color::new();
+                    for _ in 0..settings.samples_per_pixel {
+                        let u = (i as f64 + rand::random::<f64>()) / (image_width - 1) as f64;
+                        let v = (j as f64 + rand::random::<f64>()) / (image_height - 1) as f64;
+                        let mut r = match cam.get_ray(u, v) {
+                            Some(r) => r,
+                            None => continue,
+                        };
+                        if settings.spectral_mode {
+                            r.lambda = sample_wavelengths();
+                            pixel_color +=
+                                to_rgb(ray_color(r, world, settings.max_depth, true), r.lambda);
+                        } else {
+                            pixel_color += ray_color(r, world, settings.max_depth, true);
+                        }
+                    }
+                    exposure * pixel_color
+                })
+                .collect();
+
+            let done = rows_done.fetch_add(1, Ordering::Relaxed) + 1;
+            eprint!("\r{} pixels done of {} ", done * crop.width(), num_pixels);
+            stderr().flush().unwrap();
+            row
+        })
+        .collect()
+}
+
+// Value following `flag`, exiting with the usage when it is missing or does not parse
+fn flag_value<T>(
+    args: &[String],
+    flag: &str,
+    usage: &str,
+    parse: impl FnOnce(&str) -> Option<T>,
+) -> Option<T> {
+    let i = args.iter().position(|arg| arg == flag)?;
+    let value = args.get(i + 1).and_then(|value| parse(value));
+    if value.is_none() {
+        eprintln!("{} expects {}", flag, usage);
+        std::process::exit(1);
+    }
+    value
+}
+
+// Exit with a message when a file given on the command line cannot be used
+fn or_exit<T>(result: std::io::Result<T>, what: &str) -> T {
+    result.unwrap_or_else(|e| {
+        eprintln!("Could not load {}: {}", what, e);
+        std::process::exit(1);
+    })
+}
+
+// Frames covered by `a..b`, or `a..=b` to include the last one
+fn parse_frames(range: &str) -> Option<std::ops::Range<i32>> {
+    let (start, end) = range.split_once("..")?;
+    let start = start.parse::<i32>().ok()?;
+    let end = match end.strip_prefix('=') {
+        Some(last) => last.parse::<i32>().ok()?.checked_add(1)?,
+        None => end.parse::<i32>().ok()?,
+    };
+    (start < end).then_some(start..end)
+}
+
+// Two numbers separated by a comma
+fn parse_pair(value: &str) -> Option<(f64, f64)> {
+    let (a, b) = value.split_once(',')?;
+    Some((a.parse::<f64>().ok()?, b.parse::<f64>().ok()?))
+}
+
+// A name, optionally followed by a comma and a number
+fn parse_named(value: &str) -> Option<(&str, Option<f64>)> {
+    match value.split_once(',') {
+        Some((name, number)) => Some((name, Some(number.parse::<f64>().ok()?))),
+        None => Some((value, None)),
+    }
+}
+
+fn parse_projection(value: &str) -> Option<projection> {
+    match parse_named(value)? {
+        ("perspective", None) => Some(projection::Perspective),
+        ("orthographic", height) => {
+            let height = height.unwrap_or(ORTHOGRAPHIC_HEIGHT);
+            (height > 0.).then_some(projection::Orthographic(height))
+        }
+        ("fisheye", None) => Some(projection::Fisheye),
+        ("equirectangular", None) => Some(projection::Equirectangular),
+        _ => None,
+    }
+}
+
+fn parse_blades(value: &str) -> Option<aperture> {
+    let (blades, rotation) = match value.split_once(',') {
+        Some((blades, rotation)) => (blades, rotation.parse::<f64>().ok()?),
+        None => (value, 0.),
+    };
+    let blades = blades.parse::<u32>().ok().filter(|blades| *blades >= 3)?;
+    Some(aperture::polygon(blades, rotation))
+}
+
+// Layout, then an optional interocular distance and convergence distance
+fn parse_stereo(value: &str) -> Option<(stereo_layout, f64, Option<f64>)> {
+    let mut fields = value.split(',');
+    let layout = match fields.next()? {
+        "side-by-side" => stereo_layout::SideBySide,
+        "top-bottom" => stereo_layout::TopBottom,
+        _ => return None,
+    };
+    let mut distance = || match fields.next() {
+        Some(number) => number.parse::<f64>().ok().filter(|d| *d > 0.).map(Some),
+        None => Some(None),
+    };
+    let interocular = distance()?.unwrap_or(INTEROCULAR);
+    let convergence = distance()?;
+    if fields.next().is_some() {
+        return None;
+    }
+    Some((layout, interocular, convergence))
+}
+
+fn parse_focus_target(value: &str) -> Option<focus_target> {
+    match value {
+        "center" => Some(focus_target::Image(0.5, 0.5)),
+        "lookat" => Some(focus_target::Lookat),
+        _ => parse_pair(value)
+            .filter(|(s, t)| (0. ..=1.).contains(s) && (0. ..=1.).contains(t))
+            .map(|(s, t)| focus_target::Image(s, t)),
+    }
+}
+
+// What the camera focuses on with --autofocus
+enum focus_target {
+    Lookat,
+    Image(f64, f64), // whatever is seen there, in fractions of the image from the bottom left
+}
+
+// Camera settings from the command line, applied to the camera of every frame
+struct camera_options {
+    projection: Option<projection>,
+    aperture: Option<aperture>, // an aperture image wins over blades
+    cat_eye: Option<f64>,
+    film_height: Option<f64>,
+    lens: Option<lens_system>,
+    f_number: Option<f64>,
+    iso: Option<f64>, // physical exposure, for scenes with radiance in cd/m^2
+    ev: Option<f64>,
+    tilt: Option<(f64, f64)>,
+    shift: Option<(f64, f64)>,
+    stereo: Option<(stereo_layout, f64, Option<f64>)>,
+    autofocus: Option<focus_target>,
+}
+
+impl camera_options {
+    fn parse(args: &[String]) -> camera_options {
+        let number = |value: &str| value.parse::<f64>().ok();
+        let positive = |value: &str| value.parse::<f64>().ok().filter(|v| *v > 0.);
+        let strength = |value: &str| number(value).filter(|v| (0. ..=2.).contains(v));
+        let path = |value: &str| Some(value.to_string());
+
+        let blades = flag_value(
+            args,
+            "--blades",
+            "a blade count of at least 3 and an optional rotation in degrees such as 6 or 6,15",
+            parse_blades,
+        );
+        let aperture_image = flag_value(
+            args,
+            "--aperture-image",
+            "a PPM image of the aperture",
+            path,
+        )
+        .map(|path| aperture::image(&or_exit(image_texture::load(&path), &path)));
+        let lens = flag_value(args, "--lens", "a lens prescription file", path)
+            .map(|path| or_exit(lens_system::load(&path, 0.001, FULL_FRAME_DIAGONAL), &path));
+        let autofocus = args.iter().position(|arg| arg == "--autofocus").map(|i| {
+            match args.get(i + 1).filter(|value| !value.starts_with("--")) {
+                None => focus_target::Image(0.5, 0.5),
+                Some(value) => parse_focus_target(value).unwrap_or_else(|| {
+                    eprintln!(
+                        "--autofocus expects center, lookat or an image position s,t from 0 to 1"
+                    );
+                    std::process::exit(1);
+                }),
+            }
+        });
+
+        let projection = flag_value(
+            args,
+            "--projection",
+            "perspective, orthographic with an optional view height, fisheye or equirectangular",
+            parse_projection,
+        );
+        // A lens system traces its own perspective through the glass
+        if lens.is_some() && !matches!(projection, None | Some(projection::Perspective)) {
+            eprintln!("--projection cannot be combined with --lens");
+            std::process::exit(1);
+        }
+        let tilt = flag_value(
+            args,
+            "--tilt",
+            "tilt and swing angles in degrees such as 5,0",
+            parse_pair,
+        );
+        let shift = flag_value(
+            args,
+            "--shift",
+            "shifts in fractions of the view such as 0,0.2",
+            parse_pair,
+        );
+        // Movements shift and tilt the thin lens, a lens system keeps its glass centered
+        if lens.is_some() && (tilt.is_some() || shift.is_some()) {
+            eprintln!("--tilt and --shift cannot be combined with --lens");
+            std::process::exit(1);
+        }
+        // Only planar projections have a film plane for the movements to act on
+        if matches!(
+            projection,
+            Some(projection::Fisheye | projection::Equirectangular)
+        ) && (tilt.is_some() || shift.is_some())
+        {
+            eprintln!("--tilt and --shift cannot be combined with fisheye or equirectangular");
+            std::process::exit(1);
+        }
+        // A lens system images onto the full frame film it was loaded for
+        let film_height = flag_value(args, "--film-height", "a positive height", positive);
+        if lens.is_some() && film_height.is_some() {
+            eprintln!("--film-height cannot be combined with --lens");
+            std::process::exit(1);
+        }
+        let stereo = flag_value(
+            args,
+            "--stereo",
+            "side-by-side or top-bottom, then optional positive interocular and convergence",
+            parse_stereo,
+        );
+
+        camera_options {
+            projection,
+            aperture: aperture_image.or(blades),
+            cat_eye: flag_value(args, "--cat-eye", "a strength from 0 to 2", strength),
+            film_height,
+            lens,
+            f_number: flag_value(args, "--f-number", "a positive f-number", positive),
+            iso: flag_value(args, "--iso", "a positive film speed", positive),
+            ev: flag_value(args, "--ev", "an exposure compensation in stops", number),
+            tilt,
+            shift,
+            stereo,
+            autofocus,
+        }
+    }
+
+    // Settings apply in the order that keeps each one meaningful: the film before the
+    // lens and f-number, and the stereo split before autofocus looks through an eye
+    fn apply(&self, mut cam: camera, world: &hittable_list) -> camera {
+        if let Some(projection) = self.projection {
+            cam = cam.with_projection(projection);
+        }
+        if let Some(aperture) = &self.aperture {
+            cam = cam.with_aperture(aperture.clone());
+        }
+        if let Some(strength) = self.cat_eye {
+            cam = cam.with_cat_eye(strength);
+        }
+        if let Some(height) = self.film_height {
+            cam = cam.with_film_height(height);
+        }
+        if let Some(lens) = &self.lens {
+            cam = cam.with_lens_system(lens.clone());
+        }
+        if let Some(n) = self.f_number {
+            cam = cam.with_f_number(n);
+        }
+        if let Some(iso) = self.iso {
+            cam = cam.with_exposure(iso, SHUTTER_FRACTION / FRAMES_PER_SECOND);
+        }
+        if let Some(ev) = self.ev {
+            cam = cam.with_ev_compensation(ev);
+        }
+        if let Some((tilt, swing)) = self.tilt {
+            cam = cam.with_tilt(tilt, swing);
+        }
+        if let Some((x, y)) = self.shift {
+            cam = cam.with_shift(x, y);
+        }
+        if let Some((layout, interocular, _)) = self.stereo {
+            // Converge where the camera is focused, autofocus moves both
+            let convergence = cam.focus_dist();
+            cam = cam.with_stereo(interocular, convergence, layout);
+        }
+        cam = match self.autofocus {
+            Some(focus_target::Lookat) => cam.with_focus_on_lookat(),
+            Some(focus_target::Image(s, t)) => cam.with_autofocus(world, s, t),
+            None => cam,
+        };
+        // unless given a convergence distance of its own
+        if let Some((_, _, Some(convergence))) = self.stereo {
+            cam = cam.with_convergence(convergence);
+        }
+        cam
     }
 }
 
@@ -107,76 +490,114 @@ fn main() {
     let image_height: i32 = (image_width as f64 / aspect_ratio) as i32;
     let samples_per_pixel = 500;
     let max_depth = 50;
+    let args: Vec<String> = std::env::args().collect();
+    let spectral_mode = args.iter().any(|arg| arg == "--spectral");
+    let frames = flag_value(
+        &args,
+        "--frames",
+        "a non-empty range of frame numbers such as 1..241 or 1..=240",
+        parse_frames,
+    );
+    let crop_uv = args.iter().any(|arg| arg == "--crop-uv");
+    let crop = flag_value(
+        &args,
+        "--crop",
+        "x0,y0,x1,y1 from the top left, in whole pixels or with --crop-uv in fractions of \
+         the image, covering at least one pixel",
+        |window| crop_window::parse(window, crop_uv, image_width, image_height),
+    )
+    .unwrap_or_else(|| crop_window::full(image_width, image_height));
+    let border = args.iter().any(|arg| arg == "--border");
+    let scene = flag_value(&args, "--scene", libscenes::names(), |name| {
+        Some(name.to_string())
+    })
+    .unwrap_or_else(|| "random".to_string());
+    // Density grid file for the media scene
+    let density = flag_value(&args, "--density", "a density grid file", |path| {
+        Some(path.to_string())
+    });
+    // Normal map image for the materials scene
+    let normal_map = flag_value(&args, "--normal-map", "a PPM normal map", |path| {
+        Some(path.to_string())
+    });
+    let options = camera_options::parse(&args);
 
-    // World
-    let world = random_scene();
-
-    // Camera
-    let lookfrom = point3::from(13., 2., 3.);
-    let lookat = point3::from(0., 0., 0.);
-    let vup = vec3::from(0., 1., 0.);
-    let dist_to_focus = 10.;
-    let aperture = 0.1;
-
-    let cam = camera::from(
-        lookfrom,
-        lookat,
-        vup,
-        20.,
-        aspect_ratio,
-        aperture,
-        dist_to_focus,
+    let settings = render_settings {
+        image_width,
+        image_height,
+        samples_per_pixel,
+        max_depth,
+        spectral_mode,
+        crop,
+        border,
+    };
+    let (output_width, output_height) = settings.output_size();
+
+    // World and camera animation
+    let (mut world, animation) = or_exit(
+        libscenes::by_name(&scene, density.as_deref(), normal_map.as_deref()),
+        &format!("scene {}", scene),
+    );
+
+    // BVH over every instant the shutter is open, a still being frame 0
+    let shutter = SHUTTER_FRACTION / FRAMES_PER_SECOND;
+    let span = frames.clone().unwrap_or(0..1);
+    world.build_bvh(
+        span.start as f64 / FRAMES_PER_SECOND,
+        (span.end - 1) as f64 / FRAMES_PER_SECOND + shutter,
     );
 
+    // Camera for the frame at `time`, with the shutter open for part of it. Scenes lit in
+    // cd/m^2 pick their film speed, --iso overrides it.
+    let camera_at = |time: f64| {
+        let mut cam = animation
+            .camera_at(time, aspect_ratio)
+            .with_shutter(time, time + shutter);
+        if let Some(iso) = animation.iso {
+            cam = cam.with_exposure(iso, shutter);
+        }
+        let cam = options.apply(cam, &world);
+        if cam.exposure_scale().is_none() {
+            eprintln!(
+                "--iso needs an f-number to meter: --f-number, --lens or an aperture on --film-height"
+            );
+            std::process::exit(1);
+        }
+        cam
+    };
+
     // Render
     let start_time = std::time::SystemTime::now();
-    let num_pixels = image_height * image_width;
-    let pixels: Arc<Mutex<Vec<(String, i32, i32)>>> =
-        Arc::new(Mutex::new(Vec::with_capacity(num_pixels as usize)));
-
-    print!("P3\n{} {}\n255\n", image_width, image_height);
-    (0..image_height).into_par_iter().for_each(|j| {
-        eprint!(
-            "\r{} pixels done of {} ",
-            pixels.lock().unwrap().len(),
-            num_pixels
-        );
-        stderr().flush().unwrap();
-        (0..image_width).into_par_iter().for_each(|i| {
-            let mut pixel_color = color::new();
-            for _ in 0..samples_per_pixel {
-                let u = (i as f64 + rand::random::<f64>()) / (image_width - 1) as f64;
-                let v = (j as f64 + rand::random::<f64>()) / (image_height - 1) as f64;
-                let r = cam.get_ray(u, v);
-                pixel_color += ray_color(r, &world, max_depth);
-            }
-            pixels
-                .lock()
-                .unwrap()
-                .push((write_color(pixel_color, samples_per_pixel), j, i));
-        });
-    });
-    eprint!(
-        "\r{} pixels done of {} ",
-        pixels.lock().unwrap().len(),
-        num_pixels
-    );
-    stderr().flush().unwrap();
+    match frames {
+        Some(frames) => {
+            for frame in frames {
+                let cam = camera_at(frame as f64 / FRAMES_PER_SECOND);
 
-    eprint!("\nSorting pixels... ");
-    let mut pixels_vec = pixels.lock().unwrap();
-    pixels_vec.sort_by(|a, b| {
-        if a.1 == b.1 {
-            b.2.cmp(&a.2)
-        } else {
-            a.1.cmp(&b.1)
+                let pixels = settings.output_pixels(render(&cam, &world, &settings));
+                let path = format!("frame_{:04}.png", frame);
+                if let Err(e) = write_png(
+                    &path,
+                    output_width,
+                    output_height,
+                    &pixels,
+                    samples_per_pixel,
+                ) {
+                    eprintln!("\nCould not write {}: {}", path, e);
+                    std::process::exit(1);
+                }
+                eprint!("\nWrote {}", path);
+            }
         }
-    });
+        None => {
+            let cam = camera_at(0.);
+            let pixels = settings.output_pixels(render(&cam, &world, &settings));
 
-    eprint!("\nReversing pixels and printing... ");
-    pixels_vec.reverse();
-    for pixel in pixels_vec.clone() {
-        println!("{}", pixel.0);
+            eprint!("\nPrinting pixels... ");
+            print!("P3\n{} {}\n255\n", output_width, output_height);
+            for pixel in pixels {
+                println!("{}", write_color(pixel, samples_per_pixel));
+            }
+        }
     }
     eprint!(
         "\nDone! in {} seconds\n",